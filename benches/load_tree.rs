@@ -0,0 +1,96 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use github_distributed_owners::allow_filter::{AllowList, FilterGitMetadata};
+use github_distributed_owners::file_source::WorkingTree;
+use github_distributed_owners::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::{tempdir, TempDir};
+
+const OWNERS_FILE_NAMES: [&str; 1] = ["OWNERS"];
+
+fn create_file(root: &Path, path: &str, contents: &str) -> anyhow::Result<PathBuf> {
+    let full_path = root.join(path);
+    fs::create_dir_all(full_path.parent().unwrap())?;
+    fs::write(&full_path, contents)?;
+    Ok(full_path)
+}
+
+/// Creates a synthetic tree of 10,000 source files, with OWNERS files scattered through it,
+/// returning the OWNERS files.
+fn create_large_tree(temp_dir: &TempDir) -> anyhow::Result<HashSet<PathBuf>> {
+    let root = temp_dir.path();
+    let mut paths = vec![];
+    for a in 0..40 {
+        for b in 0..25 {
+            for c in 0..10 {
+                let directory = format!("dir{a}/sub{b}/leaf{c}");
+                for file in 0..10 {
+                    create_file(root, &format!("{directory}/file{file}.rs"), "")?;
+                }
+                if (a + b + c) % 4 == 0 {
+                    paths.push(format!("{directory}/OWNERS"));
+                }
+            }
+            if b % 2 == 0 {
+                paths.push(format!("dir{a}/sub{b}/OWNERS"));
+            }
+        }
+        paths.push(format!("dir{a}/OWNERS"));
+    }
+
+    let mut owners_files = HashSet::new();
+    for (i, path) in paths.iter().enumerate() {
+        let contents = if i % 3 == 0 {
+            format!("user{i}\n\n[*.rs]\nset inherit = false\nrust{i}\n")
+        } else {
+            format!("user{i}\n")
+        };
+        owners_files.insert(create_file(root, path, &contents)?);
+    }
+    Ok(owners_files)
+}
+
+fn load_large_tree(c: &mut Criterion) {
+    let temp_dir = tempdir().unwrap();
+    let owners_files = create_large_tree(&temp_dir).unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+    let allow_list = AllowList::from(owners_files, true, &OWNERS_FILE_NAMES).unwrap();
+
+    let mut group = c.benchmark_group("load_large_tree");
+    group.sample_size(20);
+    group.bench_function("parallel_walk", |b| {
+        b.iter(|| {
+            OwnersTree::load_from_files(
+                &root,
+                &LoadOptions {
+                    allow_filter: &FilterGitMetadata {},
+                    owners_file_names: &OWNERS_FILE_NAMES,
+                    submodule_mode: SubmoduleMode::Ignore,
+                    source: &WorkingTree {},
+                    cache: None,
+                },
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("from_allow_list", |b| {
+        b.iter(|| {
+            OwnersTree::load_from_files(
+                &root,
+                &LoadOptions {
+                    allow_filter: &allow_list,
+                    owners_file_names: &OWNERS_FILE_NAMES,
+                    submodule_mode: SubmoduleMode::Ignore,
+                    source: &WorkingTree {},
+                    cache: None,
+                },
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, load_large_tree);
+criterion_main!(benches);
@@ -1,13 +1,43 @@
+use crate::git;
 use anyhow::anyhow;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
 use itertools::Itertools;
 use log::{trace, warn};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::{Arc, RwLock};
 
-pub trait AllowFilter {
+// NB: Sync, as the file tree is walked in parallel.
+pub trait AllowFilter: Sync {
     fn allowed(&self, path: &Path) -> bool;
+
+    /// Whether `path` is allowed, given that its parent directory is. Used when walking the file
+    /// tree, which never descends into directories that aren't allowed.
+    fn allowed_in_walk(&self, path: &Path, _is_dir: bool) -> bool {
+        self.allowed(path)
+    }
+
+    /// All allowed OWNERS files, if known up front, which allows the tree to be built without
+    /// walking the file system.
+    fn allowed_owners_files(&self) -> Option<&HashSet<PathBuf>> {
+        None
+    }
+}
+
+impl<T: AllowFilter + ?Sized> AllowFilter for Box<T> {
+    fn allowed(&self, path: &Path) -> bool {
+        (**self).allowed(path)
+    }
+
+    fn allowed_in_walk(&self, path: &Path, is_dir: bool) -> bool {
+        (**self).allowed_in_walk(path, is_dir)
+    }
+
+    fn allowed_owners_files(&self) -> Option<&HashSet<PathBuf>> {
+        (**self).allowed_owners_files()
+    }
 }
 
 #[derive(Debug)]
@@ -24,8 +54,164 @@ impl AllowFilter for FilterGitMetadata {
     }
 }
 
+/// Name of the tool specific ignore file, which uses `.gitignore` syntax.
+pub const IGNORE_FILE_NAME: &str = ".github-distributed-owners-ignore";
+
+/// Filters out files which git would ignore, along with git metadata, for use when files are not
+/// restricted to those tracked by git.
+///
+/// This respects `.gitignore` files throughout the repo, `.git/info/exclude` and the tool specific
+/// [IGNORE_FILE_NAME] at the repo root, which takes precedence over the others. The user's global
+/// git excludes file is deliberately not used, so that results don't vary between machines.
+pub struct FilterIgnored {
+    repo_root: PathBuf,
+    tool_ignore: Gitignore,
+    git_exclude: Gitignore,
+    // The `.gitignore` files applying within each directory, deepest first, loaded lazily as
+    // directories are walked.
+    gitignores: RwLock<HashMap<PathBuf, Arc<Vec<Arc<Gitignore>>>>>,
+}
+
+impl FilterIgnored {
+    pub fn new<P: AsRef<Path>>(repo_root: P) -> anyhow::Result<FilterIgnored> {
+        let repo_root = repo_root.as_ref().canonicalize()?;
+        let tool_ignore = load_ignore_file(&repo_root, &repo_root.join(IGNORE_FILE_NAME))?;
+        let git_exclude = load_ignore_file(&repo_root, &repo_root.join(".git/info/exclude"))?;
+        Ok(FilterIgnored {
+            repo_root,
+            tool_ignore,
+            git_exclude,
+            gitignores: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// The `.gitignore` files applying to paths directly within `directory`, deepest first.
+    fn gitignores(&self, directory: &Path) -> anyhow::Result<Arc<Vec<Arc<Gitignore>>>> {
+        if let Some(gitignores) = self.gitignores.read().unwrap().get(directory) {
+            return Ok(gitignores.clone());
+        }
+        let mut gitignores = vec![Arc::new(load_ignore_file(
+            directory,
+            &directory.join(".gitignore"),
+        )?)];
+        if let Some(parent) = directory.parent() {
+            if directory != self.repo_root {
+                gitignores.extend(self.gitignores(parent)?.iter().cloned());
+            }
+        }
+        // NB: Another thread may have loaded the same directory meanwhile, which is harmless.
+        Ok(self
+            .gitignores
+            .write()
+            .unwrap()
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| Arc::new(gitignores))
+            .clone())
+    }
+
+    /// Whether `path` is ignored, either itself or through one of its parent directories. As in
+    /// git, a path can't be re-included if a parent directory is ignored, as the walk never reaches
+    /// it.
+    ///
+    /// NB: Whether `path` itself is a directory is checked in the working tree, which this filter's
+    /// ignore files are read from.
+    fn is_ignored(&self, path: &Path) -> anyhow::Result<bool> {
+        let Ok(relative_path) = path.strip_prefix(&self.repo_root) else {
+            return Ok(false);
+        };
+        let mut current = self.repo_root.clone();
+        let mut components = relative_path.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let is_dir = components.peek().is_some() || current.is_dir();
+            if self.is_ignored_itself(&current, is_dir)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether `path` matches an ignore pattern, ignoring any patterns matching its parents.
+    fn is_ignored_itself(&self, path: &Path, is_dir: bool) -> anyhow::Result<bool> {
+        let Some(directory) = path
+            .parent()
+            .filter(|directory| directory.starts_with(&self.repo_root))
+        else {
+            return Ok(false);
+        };
+        // Deeper `.gitignore` files take precedence, per git semantics.
+        let gitignores = self.gitignores(directory)?;
+        let mut matchers = vec![&self.tool_ignore];
+        matchers.extend(gitignores.iter().map(|gitignore| gitignore.as_ref()));
+        matchers.push(&self.git_exclude);
+
+        for matcher in matchers {
+            match matcher.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) => {
+                    trace!("{:?} ignored by {:?}", path, glob.from());
+                    return Ok(true);
+                }
+                Match::Whitelist(_) => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn load_ignore_file(root: &Path, path: &Path) -> anyhow::Result<Gitignore> {
+    if !path.is_file() {
+        return Ok(Gitignore::empty());
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(error) = builder.add(path) {
+        return Err(anyhow!(
+            "Failed to load ignore file '{}': {}",
+            path.display(),
+            error
+        ));
+    }
+    Ok(builder.build()?)
+}
+
+impl AllowFilter for FilterIgnored {
+    fn allowed(&self, path: &Path) -> bool {
+        if !(FilterGitMetadata {}).allowed(path) {
+            return false;
+        }
+        if !path.starts_with(&self.repo_root) {
+            return true;
+        }
+        match self.is_ignored(path) {
+            Ok(ignored) => !ignored,
+            Err(error) => {
+                warn!("Failed to check whether {:?} is ignored: {}", path, error);
+                true
+            }
+        }
+    }
+
+    fn allowed_in_walk(&self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name() == Some(OsStr::new(".git")) {
+            return false;
+        }
+        if !path.starts_with(&self.repo_root) {
+            return true;
+        }
+        // Parent directories were already checked by the walk.
+        match self.is_ignored_itself(path, is_dir) {
+            Ok(ignored) => !ignored,
+            Err(error) => {
+                warn!("Failed to check whether {:?} is ignored: {}", path, error);
+                true
+            }
+        }
+    }
+}
+
 pub struct AllowList {
     allowed_files: HashSet<PathBuf>,
+    owners_files: HashSet<PathBuf>,
     _private: (), // Force use of AllowList::from outside this package
 }
 
@@ -33,29 +219,22 @@ impl AllowFilter for AllowList {
     fn allowed(&self, path: &Path) -> bool {
         self.allowed_files.contains(path)
     }
+
+    fn allowed_owners_files(&self) -> Option<&HashSet<PathBuf>> {
+        Some(&self.owners_files)
+    }
 }
 
 impl AllowList {
-    pub fn allow_git_files() -> anyhow::Result<AllowList> {
-        let output = Command::new("git").arg("ls-files").output()?;
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Error gathering git files:\n{}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
-        let git_files: HashSet<PathBuf> = String::from_utf8_lossy(&output.stdout)
-            .lines()
-            .map(PathBuf::from)
-            // If an OWNERS file has been deleted, but the deletion has not yet been staged,
-            // an error would be thrown without filtering them out.
-            .filter(|path| if !path.exists() {
-                warn!("Missing expected git file at `{}`, possibly the deletion has not been staged?", path.display());
-                false
-            } else {
-                true
-            })
-            .collect();
+    /// Allows the OWNERS files tracked by git, along with their parent directories.
+    ///
+    /// Files in submodules are only allowed when `recurse_submodules` is set.
+    pub fn allow_git_files<P: AsRef<Path>, S: AsRef<str>>(
+        repo_root: P,
+        owners_file_names: &[S],
+        recurse_submodules: bool,
+    ) -> anyhow::Result<AllowList> {
+        let git_files = git::tracked_files(repo_root, recurse_submodules)?;
         trace!(
             "Git files:{}",
             git_files
@@ -64,23 +243,35 @@ impl AllowList {
                 .map(|p| format!("\n - {:?}", &p))
                 .join("")
         );
-        AllowList::from(git_files, true)
+        AllowList::from(git_files, true, owners_file_names)
     }
 
-    pub fn from(paths: HashSet<PathBuf>, expand: bool) -> anyhow::Result<AllowList> {
+    pub fn from<S: AsRef<str>>(
+        paths: HashSet<PathBuf>,
+        expand: bool,
+        owners_file_names: &[S],
+    ) -> anyhow::Result<AllowList> {
         let mut expanded_paths: HashSet<PathBuf> = HashSet::new();
+        let mut owners_files: HashSet<PathBuf> = HashSet::new();
         for path in paths {
-            if path.file_name() != Some(OsStr::new("OWNERS")) {
+            let is_owners_file = path.file_name().map_or(false, |file_name| {
+                owners_file_names
+                    .iter()
+                    .any(|name| file_name == OsStr::new(name.as_ref()))
+            });
+            if !is_owners_file {
                 trace!("Ignoring allowed file {:?}, not an OWNERS file", path);
                 continue;
             }
             // When walking the file tree, paths are absolute.
             // Canonicalize is needed to make these paths to match.
-            expanded_paths.insert(if expand {
+            let owners_file = if expand {
                 path.canonicalize()?
             } else {
                 path.to_path_buf()
-            });
+            };
+            expanded_paths.insert(owners_file.clone());
+            owners_files.insert(owners_file);
             let mut parent = path.parent();
             while let Some(dir) = parent {
                 if dir.as_os_str().is_empty() {
@@ -96,6 +287,7 @@ impl AllowList {
         }
         Ok(AllowList {
             allowed_files: expanded_paths,
+            owners_files,
             _private: (),
         })
     }
@@ -103,9 +295,15 @@ impl AllowList {
 
 #[cfg(test)]
 mod test {
-    use crate::allow_filter::{AllowFilter, AllowList, FilterGitMetadata};
+    use crate::allow_filter::{
+        AllowFilter, AllowList, FilterGitMetadata, FilterIgnored, IGNORE_FILE_NAME,
+    };
+    use crate::file_source::WorkingTree;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+    use crate::test_utils::create_test_file;
     use std::collections::HashSet;
     use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
 
     #[test]
     fn filter_git_metadata() {
@@ -118,13 +316,99 @@ mod test {
         assert!(!filter.allowed(Path::new(".git/hooks/pre-commit")));
     }
 
+    #[test]
+    fn filter_ignored() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(&temp_dir, ".gitignore", "target/\nnode_modules/\nvendor/\n")?;
+        create_test_file(&temp_dir, ".git/info/exclude", "local/\n")?;
+        create_test_file(
+            &temp_dir,
+            IGNORE_FILE_NAME,
+            "generated/\n!vendor/\n!target/keep/\n",
+        )?;
+        create_test_file(&temp_dir, "web/.gitignore", "dist/\n!node_modules/\n")?;
+        for path in [
+            "OWNERS",
+            "target/OWNERS",
+            "target/keep/OWNERS",
+            "vendor/OWNERS",
+            "node_modules/foo/OWNERS",
+            "local/OWNERS",
+            "generated/OWNERS",
+            "web/OWNERS",
+            "web/dist/OWNERS",
+            "web/node_modules/OWNERS",
+        ] {
+            create_test_file(&temp_dir, path, "ada.lovelace\n")?;
+        }
+        let root = temp_dir.path().canonicalize()?;
+        let filter = FilterIgnored::new(&root)?;
+
+        assert!(filter.allowed(&root.join("OWNERS")));
+        assert!(filter.allowed(&root.join("web")));
+        assert!(filter.allowed(&root.join("web/OWNERS")));
+        // Whitelisted in the tool specific ignore file, which takes precedence
+        assert!(filter.allowed(&root.join("vendor/OWNERS")));
+        // Whitelisted in a deeper .gitignore, which takes precedence
+        assert!(filter.allowed(&root.join("web/node_modules/OWNERS")));
+
+        assert!(!filter.allowed(&root.join(".git/info/exclude")));
+        assert!(!filter.allowed(&root.join("target")));
+        assert!(!filter.allowed(&root.join("target/OWNERS")));
+        // Can't be re-included, as its parent directory is ignored, as in git
+        assert!(!filter.allowed(&root.join("target/keep/OWNERS")));
+        assert!(!filter.allowed(&root.join("node_modules/foo/OWNERS")));
+        assert!(!filter.allowed(&root.join("local/OWNERS")));
+        assert!(!filter.allowed(&root.join("generated/OWNERS")));
+        assert!(!filter.allowed(&root.join("web/dist/OWNERS")));
+
+        // When walking, parent directories have already been checked
+        assert!(filter.allowed_in_walk(&root.join("web"), true));
+        assert!(filter.allowed_in_walk(&root.join("target/keep/OWNERS"), false));
+        assert!(!filter.allowed_in_walk(&root.join("target"), true));
+        assert!(!filter.allowed_in_walk(&root.join("web/dist"), true));
+        assert!(!filter.allowed_in_walk(&root.join(".git"), true));
+
+        // The same files are loaded when walking the tree
+        let tree = OwnersTree::load_from_files(
+            &root,
+            &LoadOptions {
+                allow_filter: &filter,
+                owners_file_names: &["OWNERS"],
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &WorkingTree {},
+                cache: None,
+            },
+        )?;
+        let mut owners_files = Vec::new();
+        let mut nodes = vec![&tree];
+        while let Some(node) = nodes.pop() {
+            owners_files.extend(node.owners_file.iter().cloned());
+            nodes.extend(&node.children);
+        }
+        owners_files.sort();
+        assert_eq!(
+            owners_files,
+            [
+                "OWNERS",
+                "vendor/OWNERS",
+                "web/OWNERS",
+                "web/node_modules/OWNERS"
+            ]
+            .iter()
+            .map(|path| root.join(path))
+            .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
     #[test]
     fn allow_list() {
         let allowed_files = ["Cargo.lock", "LICENSE", "OWNERS", "src/OWNERS"]
             .iter()
             .map(PathBuf::from)
             .collect::<HashSet<PathBuf>>();
-        let filter = AllowList::from(allowed_files, false).unwrap();
+        let filter = AllowList::from(allowed_files, false, &["OWNERS"]).unwrap();
         assert!(filter.allowed(Path::new("OWNERS")));
         assert!(filter.allowed(Path::new("src/OWNERS")));
 
@@ -136,5 +420,32 @@ mod test {
         assert!(!filter.allowed(Path::new(".git/hooks/pre-commit")));
         assert!(!filter.allowed(Path::new("abc/OWNERS")));
         assert!(!filter.allowed(Path::new("src/main.rs")));
+
+        let expected_owners_files = ["OWNERS", "src/OWNERS"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<HashSet<PathBuf>>();
+        assert_eq!(filter.allowed_owners_files(), Some(&expected_owners_files));
+    }
+
+    #[test]
+    fn allow_list_custom_owners_file_names() {
+        let allowed_files = [
+            "OWNERS",
+            "src/.owners",
+            "vendor/lib/OWNERS.md",
+            "docs/README.md",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<HashSet<PathBuf>>();
+        let filter = AllowList::from(allowed_files, false, &[".owners", "OWNERS.md"]).unwrap();
+        assert!(filter.allowed(Path::new("src/.owners")));
+        assert!(filter.allowed(Path::new("vendor/lib/OWNERS.md")));
+        assert!(filter.allowed(Path::new("vendor/lib")));
+
+        // Not an accepted OWNERS file name, so ignored
+        assert!(!filter.allowed(Path::new("OWNERS")));
+        assert!(!filter.allowed(Path::new("docs/README.md")));
     }
 }
@@ -0,0 +1,171 @@
+use crate::policy::PolicyViolation;
+use anyhow::anyhow;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Bumped whenever the baseline format, or how violations are keyed, changes.
+const BASELINE_VERSION: u32 = 1;
+
+/// Known violations, which don't fail the run, so that new rules can be introduced while existing
+/// violations are fixed over time.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct Baseline {
+    version: u32,
+    violations: BTreeSet<BaselineEntry>,
+}
+
+/// A violation, keyed by its rule, OWNERS file and subject rather than line numbers or the
+/// message, so that unrelated edits to the OWNERS file don't invalidate it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct BaselineEntry {
+    rule_id: String,
+    owners_file: Option<String>,
+    subject: String,
+}
+
+impl From<&PolicyViolation> for BaselineEntry {
+    fn from(violation: &PolicyViolation) -> BaselineEntry {
+        BaselineEntry {
+            rule_id: violation.rule_id.clone(),
+            owners_file: violation.owners_file.clone(),
+            subject: violation.subject.clone(),
+        }
+    }
+}
+
+impl Baseline {
+    pub fn from_violations(violations: &[PolicyViolation]) -> Baseline {
+        Baseline {
+            version: BASELINE_VERSION,
+            violations: violations.iter().map(BaselineEntry::from).collect(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Baseline> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|error| anyhow!("Failed to read baseline '{}': {}", path.display(), error))?;
+        let baseline: Baseline = serde_json::from_str(&text)
+            .map_err(|error| anyhow!("Failed to parse baseline '{}': {}", path.display(), error))?;
+        if baseline.version != BASELINE_VERSION {
+            return Err(anyhow!(
+                "Baseline '{}' is from a different version, and needs writing again",
+                path.display()
+            ));
+        }
+        Ok(baseline)
+    }
+
+    /// Writes the baseline as sorted, pretty printed JSON, so that changes to it diff cleanly.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn violation_count(&self) -> usize {
+        self.violations.len()
+    }
+
+    /// The `violations` which aren't in the baseline.
+    pub fn new_violations(&self, violations: Vec<PolicyViolation>) -> Vec<PolicyViolation> {
+        let entries = violations
+            .iter()
+            .map(BaselineEntry::from)
+            .collect::<BTreeSet<_>>();
+        let fixed = self.violations.difference(&entries).count();
+        if fixed > 0 {
+            info!(
+                "{} violations in the baseline no longer occur, and can be removed by writing it \
+                again",
+                fixed
+            );
+        }
+        violations
+            .into_iter()
+            .filter(|violation| !self.violations.contains(&BaselineEntry::from(violation)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::baseline::Baseline;
+    use crate::policy::PolicyViolation;
+    use tempfile::tempdir;
+
+    fn violation(
+        rule_id: &str,
+        owners_file: &str,
+        subject: &str,
+        message: &str,
+    ) -> PolicyViolation {
+        PolicyViolation {
+            rule_id: rule_id.to_string(),
+            owners_file: Some(owners_file.to_string()),
+            subject: subject.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_new_violations() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("baseline.json");
+        let existing = vec![
+            violation(
+                "no-emails",
+                "/legacy/OWNERS",
+                "ada@example.com",
+                "ada@example.com is an email address",
+            ),
+            violation(
+                "no-emails",
+                "/other/OWNERS",
+                "grace@example.com",
+                "grace@example.com is an email address",
+            ),
+        ];
+        Baseline::from_violations(&existing).save(&path)?;
+        let baseline = Baseline::load(&path)?;
+        assert_eq!(baseline, Baseline::from_violations(&existing));
+        assert_eq!(baseline.violation_count(), 2);
+
+        let new = violation(
+            "no-emails",
+            "/legacy/OWNERS",
+            "mary@example.com",
+            "mary@example.com is an email address",
+        );
+        let moved = violation(
+            "no-emails",
+            "/moved/OWNERS",
+            "ada@example.com",
+            "ada@example.com is an email address",
+        );
+        let violations = vec![existing[0].clone(), new.clone(), moved.clone()];
+        assert_eq!(baseline.new_violations(violations), vec![new, moved]);
+
+        // A changed message for the same subject is still known
+        let baseline = Baseline::from_violations(&[violation(
+            "min-owners",
+            "/foo/OWNERS",
+            "/foo/",
+            "/foo/ has 1 individual and 0 teams, fewer than 2 individuals",
+        )]);
+        let changed = violation(
+            "min-owners",
+            "/foo/OWNERS",
+            "/foo/",
+            "/foo/ has 0 individuals and 0 teams, fewer than 2 individuals",
+        );
+        assert!(baseline.new_violations(vec![changed]).is_empty());
+
+        // Written from a different version
+        std::fs::write(&path, r#"{"version": 0, "violations": []}"#)?;
+        assert!(Baseline::load(&path).is_err());
+        Ok(())
+    }
+}
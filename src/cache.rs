@@ -0,0 +1,315 @@
+use crate::file_source::FileSource;
+use crate::git;
+use crate::owners_file::{OwnersFileConfig, ParseDependencies};
+use git2::{ObjectType, Oid};
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Name of the cache file, stored in the repository's git directory so it is never committed.
+pub const CACHE_FILE_NAME: &str = "github-distributed-owners-cache.json";
+
+/// Bumped whenever the cached data, or how it is interpreted, changes.
+const CACHE_VERSION: u32 = 3;
+
+/// Hashes file contents the same way git hashes blobs.
+pub fn content_hash(text: &str) -> String {
+    Oid::hash_object(ObjectType::Blob, text.as_bytes())
+        .expect("Failed to hash content")
+        .to_string()
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheData {
+    version: u32,
+    entries: BTreeMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CacheEntry {
+    repo_base: PathBuf,
+    hash: String,
+    dependencies: ParseDependencies,
+    config: OwnersFileConfig,
+}
+
+/// On-disk cache of parsed OWNERS files, keyed by path and content hash.
+///
+/// Entries are only reused if the hashes of any included files, and the matches of any include
+/// globs, are also unchanged.
+pub struct ParseCache {
+    /// Where the cache is saved. Kept in memory only when not set.
+    path: Option<PathBuf>,
+    entries: Mutex<BTreeMap<PathBuf, CacheEntry>>,
+    used: Mutex<HashSet<PathBuf>>,
+    changed: AtomicBool,
+}
+
+impl ParseCache {
+    /// Loads the cache from `path`. A missing or unreadable cache is treated as empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> ParseCache {
+        let path = path.as_ref();
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str::<CacheData>(&text) {
+                Ok(data) if data.version == CACHE_VERSION => data.entries,
+                Ok(_) => {
+                    debug!("Ignoring cache {:?} from a different version", path);
+                    BTreeMap::new()
+                }
+                Err(error) => {
+                    warn!("Ignoring invalid cache {:?}: {}", path, error);
+                    BTreeMap::new()
+                }
+            },
+            Err(error) => {
+                debug!("No cache loaded from {:?}: {}", path, error);
+                BTreeMap::new()
+            }
+        };
+        ParseCache {
+            path: Some(path.to_path_buf()),
+            entries: Mutex::new(entries),
+            used: Mutex::new(HashSet::new()),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// A cache which is never saved, for reuse within a single process.
+    pub fn in_memory() -> ParseCache {
+        ParseCache {
+            path: None,
+            entries: Mutex::new(BTreeMap::new()),
+            used: Mutex::new(HashSet::new()),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Loads the cache for the git repository containing `repo_root`, if there is one.
+    pub fn for_repo<P: AsRef<Path>>(repo_root: P) -> Option<ParseCache> {
+        match git::open_repository(repo_root) {
+            Ok(repo) => Some(ParseCache::load(repo.path().join(CACHE_FILE_NAME))),
+            Err(error) => {
+                debug!("Not using a cache: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Parses the OWNERS file at `path` in `source`, reusing the cached result if it is still
+    /// valid.
+    pub fn parse<P0: AsRef<Path>, P1: AsRef<Path>>(
+        &self,
+        source: &dyn FileSource,
+        path: P0,
+        repo_base: P1,
+    ) -> anyhow::Result<OwnersFileConfig> {
+        Ok(self.parse_with_dependencies(source, path, repo_base)?.0)
+    }
+
+    /// As `parse`, also returning what the result depends on.
+    pub fn parse_with_dependencies<P0: AsRef<Path>, P1: AsRef<Path>>(
+        &self,
+        source: &dyn FileSource,
+        path: P0,
+        repo_base: P1,
+    ) -> anyhow::Result<(OwnersFileConfig, ParseDependencies)> {
+        let path = path.as_ref();
+        let repo_base = repo_base.as_ref();
+        let text = source.read_to_string(path)?;
+        let hash = content_hash(&text);
+        self.used.lock().unwrap().insert(path.to_path_buf());
+
+        let cached = self.entries.lock().unwrap().get(path).cloned();
+        if let Some(entry) = cached {
+            if entry.hash == hash
+                && entry.repo_base == repo_base
+                && entry.dependencies.is_current(source, repo_base)
+            {
+                trace!("Using cached parse of {:?}", path);
+                return Ok((entry.config, entry.dependencies));
+            }
+        }
+
+        debug!("Parsing {:?}", path);
+        let (config, dependencies) =
+            OwnersFileConfig::from_text_with_dependencies(source, &text, path, repo_base)?;
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            CacheEntry {
+                repo_base: repo_base.to_path_buf(),
+                hash,
+                dependencies: dependencies.clone(),
+                config: config.clone(),
+            },
+        );
+        self.changed.store(true, Ordering::Relaxed);
+        Ok((config, dependencies))
+    }
+
+    /// Whether a change to `path` could affect any cached parse, other than through the OWNERS
+    /// file itself.
+    pub fn is_dependency<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.entries.lock().unwrap().values().any(|entry| {
+            entry
+                .dependencies
+                .depends_on(&entry.repo_base, path.as_ref())
+        })
+    }
+
+    /// The OWNERS files parsed since the last save, each with the files it includes, directly or
+    /// through other included files.
+    pub fn included_files(&self) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+        let used = self.used.lock().unwrap();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| used.contains(*path))
+            .map(|(path, entry)| {
+                let included = entry.dependencies.files.keys().cloned().collect();
+                (path.clone(), included)
+            })
+            .collect()
+    }
+
+    /// Writes the cache back to disk, if anything changed. Entries for OWNERS files which weren't
+    /// used since the last save are dropped.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut used = self.used.lock().unwrap();
+        let mut entries = self.entries.lock().unwrap();
+        let entry_count = entries.len();
+        entries.retain(|path, _| used.contains(path));
+        // Start afresh, for when the same cache is reused for another run.
+        used.clear();
+        if !self.changed.swap(false, Ordering::Relaxed) && entries.len() == entry_count {
+            return Ok(());
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let data = CacheData {
+            version: CACHE_VERSION,
+            entries: entries.clone(),
+        };
+        // Write then rename, so concurrent runs never see a partially written cache.
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string(&data)?)?;
+        fs::rename(&temp_path, path)?;
+        debug!("Saved cache to {:?}", path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ParseCache;
+    use crate::file_source::WorkingTree;
+    use crate::owners_file::OwnersFileConfig;
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn reuses_unchanged_parse() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        let root = temp_dir.path().canonicalize()?;
+        let cache_path = root.join("cache.json");
+
+        let cache = ParseCache::load(&cache_path);
+        let config = cache.parse(&WorkingTree {}, root.join("OWNERS"), &root)?;
+        cache.save()?;
+        let saved = fs::metadata(&cache_path)?.modified()?;
+
+        let cache = ParseCache::load(&cache_path);
+        assert_eq!(
+            cache.parse(&WorkingTree {}, root.join("OWNERS"), &root)?,
+            config
+        );
+        cache.save()?;
+        // Nothing changed, so the cache isn't rewritten
+        assert_eq!(fs::metadata(&cache_path)?.modified()?, saved);
+
+        create_test_file(&temp_dir, "OWNERS", "grace.hopper\n")?;
+        let cache = ParseCache::load(&cache_path);
+        assert_eq!(
+            cache.parse(&WorkingTree {}, root.join("OWNERS"), &root)?,
+            OwnersFileConfig::from_file(&WorkingTree {}, root.join("OWNERS"), &root)?
+        );
+        assert_ne!(
+            cache.parse(&WorkingTree {}, root.join("OWNERS"), &root)?,
+            config
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalidated_by_included_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {"\
+                ada.lovelace
+                include /teams/*/OWNERS
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "teams/a/OWNERS", "grace.hopper\n")?;
+        let root = temp_dir.path().canonicalize()?;
+        let cache_path = root.join("cache.json");
+        let owners_file = root.join("OWNERS");
+
+        let cache = ParseCache::load(&cache_path);
+        cache.parse(&WorkingTree {}, &owners_file, &root)?;
+        cache.save()?;
+
+        // Included file changed
+        create_test_file(&temp_dir, "teams/a/OWNERS", "margaret.hamilton\n")?;
+        let cache = ParseCache::load(&cache_path);
+        let config = cache.parse(&WorkingTree {}, &owners_file, &root)?;
+        assert!(config.all_files.owners.contains("margaret.hamilton"));
+        assert!(!config.all_files.owners.contains("grace.hopper"));
+        cache.save()?;
+
+        // Glob matches a new file
+        create_test_file(&temp_dir, "teams/b/OWNERS", "katherine.johnson\n")?;
+        let cache = ParseCache::load(&cache_path);
+        let config = cache.parse(&WorkingTree {}, &owners_file, &root)?;
+        assert!(config.all_files.owners.contains("katherine.johnson"));
+        cache.save()?;
+
+        // Included file removed
+        fs::remove_file(root.join("teams/a/OWNERS"))?;
+        let cache = ParseCache::load(&cache_path);
+        let config = cache.parse(&WorkingTree {}, &owners_file, &root)?;
+        assert!(!config.all_files.owners.contains("margaret.hamilton"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_cache_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "cache.json", "not json")?;
+        let root = temp_dir.path().canonicalize()?;
+
+        let cache = ParseCache::load(root.join("cache.json"));
+        assert_eq!(
+            cache.parse(&WorkingTree {}, root.join("OWNERS"), &root)?,
+            OwnersFileConfig::from_file(&WorkingTree {}, root.join("OWNERS"), &root)?
+        );
+        cache.save()?;
+        assert!(ParseCache::load(root.join("cache.json"))
+            .entries
+            .lock()
+            .unwrap()
+            .contains_key(&root.join("OWNERS")));
+        Ok(())
+    }
+}
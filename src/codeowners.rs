@@ -1,62 +1,327 @@
 use crate::owners_tree::{OwnersTree, TreeNode};
+use anyhow::anyhow;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-pub fn to_codeowners_string(codeowners: HashMap<String, HashSet<String>>) -> String {
+/// The OWNERS file each generated rule comes from, by pattern.
+pub type RuleSources = HashMap<String, Option<PathBuf>>;
+
+/// The comment written before each rule, by pattern.
+pub type RuleComments = HashMap<String, String>;
+
+/// The comment for rules which aren't from any OWNERS file, e.g., rules added for generated files.
+const NO_SOURCE_COMMENT: &str = "# Not from an OWNERS file";
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct CodeownersRule {
+    pub pattern: String,
+    pub owners: Vec<String>,
+}
+
+/// Converts the generated ownership into CODEOWNERS rules, in the order they should be written.
+pub fn to_codeowners_rules(codeowners: &HashMap<String, HashSet<String>>) -> Vec<CodeownersRule> {
     codeowners
         .keys()
         .sorted()
-        .map(|pattern| {
-            let mut line = pattern.to_string();
-            if line == "/" {
+        .map(|key| {
+            let mut pattern = key.to_string();
+            if pattern == "/" {
                 // Unlike non-root directories, the repo root directory cannot be used as a catch all path.
                 // Instead, you have to use `*` at the root directory to achieve the same results.
                 // https://docs.github.com/en/repositories/managing-your-repositorys-settings-and-features/customizing-your-repository/about-code-owners
-                line = "*".to_string();
+                pattern = "*".to_string();
             }
             let owners = codeowners
-                .get(pattern)
+                .get(key)
                 .unwrap()
                 .iter()
                 .sorted()
-                .map(|owner| {
-                    // CODEOWNERS syntax can take any of the following formats:
-                    // - @<username>
-                    // - user@email.tld
-                    // - @org/group
-                    // For non-email versions, we can safely protect against errors
-                    // by prepending an @
-                    if owner.contains('@') {
-                        owner.to_string()
-                    } else {
-                        format!("@{}", owner)
-                    }
-                })
-                .join(" ");
-            if !owners.is_empty() {
-                line = format!("{} {}", line, owners);
-            }
-            line
+                .map(|owner| to_codeowners_owner(owner))
+                .collect();
+            CodeownersRule { pattern, owners }
         })
         // Don't include a root level owner line if no owners are specified
-        .filter(|line| line != "*")
+        .filter(|rule| rule.pattern != "*" || !rule.owners.is_empty())
+        .collect()
+}
+
+/// Renders an owner, as written in an OWNERS file, the way CODEOWNERS expects.
+pub fn to_codeowners_owner(owner: &str) -> String {
+    // CODEOWNERS syntax can take any of the following formats:
+    // - @<username>
+    // - user@email.tld
+    // - @org/group
+    // For non-email versions, we can safely protect against errors
+    // by prepending an @
+    if owner.contains('@') {
+        owner.to_string()
+    } else {
+        format!("@{}", owner)
+    }
+}
+
+/// Whether the owner is an email address, rather than a user or team.
+pub fn is_email(owner: &str) -> bool {
+    owner.contains('@') && !owner.starts_with('@')
+}
+
+/// Whether the owner is a team, written as `org/team` or `@org/team`, either in an OWNERS file or
+/// as rendered in CODEOWNERS. Anything else, including email addresses, is an individual.
+pub fn is_team(owner: &str) -> bool {
+    !is_email(owner) && owner.contains('/')
+}
+
+fn to_rule_line(rule: &CodeownersRule) -> String {
+    if rule.owners.is_empty() {
+        rule.pattern.to_string()
+    } else {
+        format!("{} {}", rule.pattern, rule.owners.join(" "))
+    }
+}
+
+pub fn to_codeowners_string(codeowners: HashMap<String, HashSet<String>>) -> String {
+    to_codeowners_rules(&codeowners)
+        .iter()
+        .map(to_rule_line)
         .join("\n")
 }
 
+/// Renders the CODEOWNERS rules with a comment before each group of consecutive rules sharing the
+/// same comment, separated by blank lines. Rules keep their usual order, so the same comment may
+/// appear more than once, e.g., when a subdirectory's rules sort between its parent's.
+pub fn to_codeowners_string_with_comments(
+    codeowners: HashMap<String, HashSet<String>>,
+    comments: &RuleComments,
+) -> String {
+    let mut lines = Vec::new();
+    let mut previous_comment = None;
+    for rule in to_codeowners_rules(&codeowners) {
+        let key = if rule.pattern == "*" {
+            "/"
+        } else {
+            rule.pattern.as_str()
+        };
+        let comment = comments.get(key).map_or(NO_SOURCE_COMMENT, String::as_str);
+        if previous_comment != Some(comment) {
+            if previous_comment.is_some() {
+                lines.push(String::new());
+            }
+            lines.push(comment.to_string());
+            previous_comment = Some(comment);
+        }
+        lines.push(to_rule_line(&rule));
+    }
+    lines.join("\n")
+}
+
+/// Comments naming the OWNERS file each rule comes from, relative to the root of the tree, followed
+/// by the files it includes, each indented under the file which included it.
+pub fn source_comments(owners_tree: &OwnersTree, sources: &RuleSources) -> RuleComments {
+    let root = &owners_tree.path;
+    let display_path = |path: &Path| match path.strip_prefix(root) {
+        Ok(relative_path) => format!("/{}", relative_path.to_string_lossy()),
+        Err(_) => path.to_string_lossy().to_string(),
+    };
+
+    let mut owners_file_comments = HashMap::new();
+    let mut nodes = vec![owners_tree];
+    while let Some(node) = nodes.pop() {
+        nodes.extend(&node.children);
+        let Some(owners_file) = &node.owners_file else {
+            continue;
+        };
+        let mut included: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+        for (include, included_by) in &node.includes {
+            included.entry(included_by).or_default().push(include);
+        }
+        let mut lines = vec![format!("# {}", display_path(owners_file))];
+        // Depth first, so each file's includes directly follow it.
+        let mut stack = vec![(owners_file.as_path(), 0)];
+        while let Some((path, depth)) = stack.pop() {
+            if depth > 0 {
+                lines.push(format!(
+                    "# {}includes {}",
+                    "  ".repeat(depth),
+                    display_path(path)
+                ));
+            }
+            if let Some(includes) = included.get(path) {
+                stack.extend(includes.iter().rev().map(|include| (*include, depth + 1)));
+            }
+        }
+        owners_file_comments.insert(owners_file, lines.join("\n"));
+    }
+
+    sources
+        .iter()
+        .filter_map(|(pattern, source)| {
+            let comment = owners_file_comments.get(source.as_ref()?)?;
+            Some((pattern.clone(), comment.clone()))
+        })
+        .collect()
+}
+
+pub fn to_json_string(codeowners: HashMap<String, HashSet<String>>) -> anyhow::Result<String> {
+    #[derive(Serialize)]
+    struct JsonCodeowners {
+        rules: Vec<CodeownersRule>,
+    }
+
+    Ok(serde_json::to_string_pretty(&JsonCodeowners {
+        rules: to_codeowners_rules(&codeowners),
+    })?)
+}
+
+/// Finds the owners of files from CODEOWNERS rules, the way GitHub does, where the last matching
+/// rule takes precedence.
+pub struct CodeownersMatcher {
+    rules: Vec<CodeownersRule>,
+    patterns: Gitignore,
+    // NB: Patterns are unique, so each identifies its rule.
+    rule_indexes: HashMap<String, usize>,
+}
+
+impl CodeownersMatcher {
+    pub fn new(rules: Vec<CodeownersRule>) -> anyhow::Result<CodeownersMatcher> {
+        // CODEOWNERS patterns follow most of the same rules as `.gitignore` patterns.
+        let mut builder = GitignoreBuilder::new("");
+        let mut rule_indexes = HashMap::new();
+        for (index, rule) in rules.iter().enumerate() {
+            builder.add_line(None, &rule.pattern)?;
+            rule_indexes.insert(rule.pattern.clone(), index);
+        }
+        Ok(CodeownersMatcher {
+            rules,
+            patterns: builder.build()?,
+            rule_indexes,
+        })
+    }
+
+    /// The owners of the file at `path`, relative to the repo root. Files matching no rule, or a
+    /// rule without owners, have no owners.
+    pub fn owners<P: AsRef<Path>>(&self, path: P) -> &[String] {
+        match self.matching_rule(path) {
+            Some(rule) => &rule.owners,
+            None => &[],
+        }
+    }
+
+    /// The rule which applies to the file at `path`, relative to the repo root, if any.
+    pub fn matching_rule<P: AsRef<Path>>(&self, path: P) -> Option<&CodeownersRule> {
+        // A rule matching a directory applies to everything in it, so the file and each of its
+        // parent directories are checked for the last matching rule.
+        let last_match = path
+            .as_ref()
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .enumerate()
+            .filter_map(
+                |(depth, ancestor)| match self.patterns.matched(ancestor, depth > 0) {
+                    Match::Ignore(glob) => self.rule_indexes.get(glob.original()).copied(),
+                    _ => None,
+                },
+            )
+            .max();
+        last_match.map(|index| &self.rules[index])
+    }
+}
+
 pub fn generate_codeowners(
     owners_tree: &OwnersTree,
     implicit_inherit: bool,
 ) -> anyhow::Result<HashMap<String, HashSet<String>>> {
+    let (codeowners, _) = generate_codeowners_with_sources(owners_tree, implicit_inherit)?;
+    Ok(codeowners)
+}
+
+/// Generates the CODEOWNERS rules, along with the OWNERS file each rule comes from. The rule for
+/// the root has no source when there is no OWNERS file there.
+pub fn generate_codeowners_with_sources(
+    owners_tree: &OwnersTree,
+    implicit_inherit: bool,
+) -> anyhow::Result<(HashMap<String, HashSet<String>>, RuleSources)> {
     let mut codeowners = HashMap::new();
+    let mut sources = HashMap::new();
     add_codeowners(
         owners_tree,
         &owners_tree.path,
         &HashSet::default(),
         implicit_inherit,
         &mut codeowners,
+        &mut sources,
     )?;
-    Ok(codeowners)
+    Ok((codeowners, sources))
+}
+
+/// The pattern for the directory at `path`, relative to `root_path`.
+fn directory_pattern(path: &Path, root_path: &Path) -> anyhow::Result<String> {
+    let mut relative_path = path.strip_prefix(root_path)?.to_string_lossy().to_string() + "/";
+    // Always use explicit paths from root
+    if !relative_path.starts_with('/') {
+        relative_path = format!("/{}", relative_path);
+    }
+    Ok(relative_path)
+}
+
+/// Adds a rule for each OWNERS file, owned by the directory level owners of its directory, so that
+/// they can always edit it. Fails if a pattern override still takes precedence over one of these
+/// rules, i.e., matches the OWNERS file and sorts after its rule, e.g., `[[A-Z]*]`.
+pub fn add_owners_file_rules(
+    owners_tree: &OwnersTree,
+    codeowners: &mut HashMap<String, HashSet<String>>,
+    sources: &mut RuleSources,
+) -> anyhow::Result<()> {
+    let mut protected = Vec::new();
+    let mut nodes = vec![owners_tree];
+    while let Some(node) = nodes.pop() {
+        nodes.extend(&node.children);
+        let Some(owners_file) = &node.owners_file else {
+            continue;
+        };
+        let owners = codeowners
+            .get(&directory_pattern(&node.path, &owners_tree.path)?)
+            .cloned()
+            .unwrap_or_default();
+        let relative_path = owners_file.strip_prefix(&owners_tree.path)?;
+        let pattern = format!("/{}", relative_path.to_string_lossy());
+        sources.insert(pattern.clone(), Some(owners_file.clone()));
+        codeowners.insert(pattern.clone(), owners);
+        protected.push((relative_path, pattern));
+    }
+
+    // The last matching rule wins, so any rule sorting after an OWNERS file's rule and matching it
+    // would take the file away from its directory's owners.
+    let matcher = CodeownersMatcher::new(to_codeowners_rules(codeowners))?;
+    for (relative_path, pattern) in protected {
+        if let Some(rule) = matcher.matching_rule(relative_path) {
+            if rule.pattern != pattern {
+                return Err(anyhow!(
+                    "{} takes precedence over {}, so its directory's owners can't edit it. \
+                    Narrow the pattern so that it doesn't match the OWNERS file.",
+                    rule.pattern,
+                    pattern
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a rule without owners for each of the `paths`, relative to the repo root, e.g., for
+/// generated files, so that changing them needs no particular approval.
+pub fn add_unowned_rules<I, P>(paths: I, codeowners: &mut HashMap<String, HashSet<String>>)
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    for path in paths {
+        let pattern = format!("/{}", path.as_ref().to_string_lossy());
+        codeowners.insert(pattern, HashSet::new());
+    }
 }
 
 fn add_codeowners(
@@ -65,19 +330,11 @@ fn add_codeowners(
     parent_owners: &HashSet<String>,
     implicit_inherit: bool,
     codeowners: &mut HashMap<String, HashSet<String>>,
+    sources: &mut RuleSources,
 ) -> anyhow::Result<()> {
     let owners_config = &tree_node.owners_config;
     let owners_set = &owners_config.all_files;
-    let mut relative_path = tree_node
-        .path
-        .strip_prefix(root_path)?
-        .to_string_lossy()
-        .to_string()
-        + "/";
-    // Always use explicit paths from root
-    if !relative_path.starts_with('/') {
-        relative_path = format!("/{}", relative_path);
-    }
+    let relative_path = directory_pattern(&tree_node.path, root_path)?;
 
     // Gather directory level owners
     let mut owners = HashSet::default();
@@ -88,6 +345,15 @@ fn add_codeowners(
 
     // Add directory level ownership
     codeowners.insert(relative_path.clone(), owners.clone());
+    sources.insert(relative_path.clone(), tree_node.owners_file.clone());
+
+    // Submodules are single paths, so no trailing slash. Added before the overrides, so that an
+    // explicit section for the submodule takes precedence.
+    for submodule in &tree_node.opaque_submodules {
+        let pattern = format!("/{}", submodule.strip_prefix(root_path)?.to_string_lossy());
+        sources.insert(pattern.clone(), tree_node.owners_file.clone());
+        codeowners.insert(pattern, owners.clone());
+    }
 
     // Add overrides
     for (override_pattern, override_owners_set) in &owners_config.pattern_overrides {
@@ -99,11 +365,19 @@ fn add_codeowners(
         }
         let mut pattern = relative_path.to_owned();
         pattern.push_str(override_pattern.as_str());
+        sources.insert(pattern.clone(), tree_node.owners_file.clone());
         codeowners.insert(pattern, override_owners);
     }
 
     for child in &tree_node.children {
-        add_codeowners(child, root_path, &owners, implicit_inherit, codeowners)?;
+        add_codeowners(
+            child,
+            root_path,
+            &owners,
+            implicit_inherit,
+            codeowners,
+            sources,
+        )?;
     }
 
     Ok(())
@@ -111,12 +385,17 @@ fn add_codeowners(
 
 #[cfg(test)]
 mod test {
-    use crate::codeowners::{generate_codeowners, to_codeowners_string};
+    use crate::codeowners::{
+        add_owners_file_rules, add_unowned_rules, generate_codeowners,
+        generate_codeowners_with_sources, is_email, is_team, source_comments, to_codeowners_owner,
+        to_codeowners_rules, to_codeowners_string, to_codeowners_string_with_comments,
+        CodeownersMatcher,
+    };
     use crate::owners_file::OwnersFileConfig;
     use crate::owners_set::OwnersSet;
     use crate::owners_tree::TreeNode;
     use indoc::indoc;
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet};
     use std::path::PathBuf;
 
     #[test]
@@ -124,6 +403,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -131,6 +413,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::default(),
             },
@@ -158,6 +441,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -165,12 +451,16 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::default(),
             },
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: None,
@@ -178,6 +468,7 @@ mod test {
                             .iter()
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::default(),
                 },
@@ -220,6 +511,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -227,6 +521,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::from([(
                     "*.rs".to_string(),
@@ -277,6 +572,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -284,6 +582,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::from([(
                     "*.rs".to_string(),
@@ -299,6 +598,9 @@ mod test {
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: None,
@@ -306,6 +608,7 @@ mod test {
                             .iter()
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::from([(
                         "*.rs".to_string(),
@@ -366,6 +669,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -373,6 +679,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::from([(
                     "*.rs".to_string(),
@@ -388,6 +695,9 @@ mod test {
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: None,
@@ -395,6 +705,7 @@ mod test {
                             .iter()
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::from([(
                         "*.rs".to_string(),
@@ -455,6 +766,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -462,6 +776,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::from([(
                     "*.rs".to_string(),
@@ -471,12 +786,16 @@ mod test {
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
                         inherit: Some(false),
+                        expiries: BTreeMap::new(),
                     },
                 )]),
             },
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: Some(false),
@@ -484,6 +803,7 @@ mod test {
                             .iter()
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::from([(
                         "*.rs".to_string(),
@@ -544,6 +864,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -551,6 +874,7 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::from([(
                     "*.rs".to_string(),
@@ -560,12 +884,16 @@ mod test {
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
                         inherit: Some(true),
+                        expiries: BTreeMap::new(),
                     },
                 )]),
             },
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: Some(true),
@@ -573,6 +901,7 @@ mod test {
                             .iter()
                             .map(|s| s.to_string())
                             .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::from([(
                         "*.rs".to_string(),
@@ -633,6 +962,9 @@ mod test {
         let tree_node = TreeNode {
             path: PathBuf::from("/tree/root"),
             repo_base: PathBuf::from("/tree/root"),
+            opaque_submodules: vec![],
+            owners_file: None,
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     inherit: None,
@@ -640,16 +972,21 @@ mod test {
                         .iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
                 pattern_overrides: HashMap::default(),
             },
             children: vec![TreeNode {
                 path: PathBuf::from("/tree/root/foo/bar"),
                 repo_base: PathBuf::from("/tree/root"),
+                opaque_submodules: vec![],
+                owners_file: None,
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         inherit: Some(false),
                         owners: HashSet::default(),
+                        expiries: BTreeMap::new(),
                     },
                     pattern_overrides: HashMap::default(),
                 },
@@ -676,6 +1013,292 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn generate_codeowners_opaque_submodule() -> anyhow::Result<()> {
+        let tree_node = TreeNode {
+            path: PathBuf::from("/tree/root"),
+            repo_base: PathBuf::from("/tree/root"),
+            owners_file: None,
+            includes: BTreeMap::new(),
+            opaque_submodules: vec![
+                PathBuf::from("/tree/root/vendor/lib"),
+                PathBuf::from("/tree/root/vendor/other"),
+            ],
+            owners_config: OwnersFileConfig {
+                all_files: OwnersSet {
+                    inherit: None,
+                    owners: vec!["ada.lovelace"]
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
+                },
+                pattern_overrides: HashMap::from([(
+                    "vendor/other".to_string(),
+                    OwnersSet {
+                        inherit: Some(false),
+                        owners: vec!["grace.hopper"]
+                            .iter()
+                            .map(|s| s.to_string())
+                            .collect::<HashSet<String>>(),
+                        expiries: BTreeMap::new(),
+                    },
+                )]),
+            },
+            children: vec![],
+        };
+        let implicit_inherit = true;
+
+        let expected = HashMap::from([
+            (
+                "/".to_string(),
+                vec!["ada.lovelace"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<HashSet<String>>(),
+            ),
+            (
+                "/vendor/lib".to_string(),
+                vec!["ada.lovelace"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<HashSet<String>>(),
+            ),
+            // Explicit section for the submodule takes precedence
+            (
+                "/vendor/other".to_string(),
+                vec!["grace.hopper"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<HashSet<String>>(),
+            ),
+        ]);
+
+        let codeowners = generate_codeowners(&tree_node, implicit_inherit)?;
+
+        assert_eq!(codeowners, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn protect_owners_files() -> anyhow::Result<()> {
+        let tree_node = TreeNode {
+            path: PathBuf::from("/tree/root"),
+            repo_base: PathBuf::from("/tree/root"),
+            owners_file: Some(PathBuf::from("/tree/root/OWNERS")),
+            includes: BTreeMap::new(),
+            opaque_submodules: vec![],
+            owners_config: OwnersFileConfig {
+                all_files: OwnersSet {
+                    inherit: None,
+                    owners: HashSet::from(["ada.lovelace".to_string()]),
+                    expiries: BTreeMap::new(),
+                },
+                pattern_overrides: HashMap::default(),
+            },
+            children: vec![TreeNode {
+                path: PathBuf::from("/tree/root/foo"),
+                repo_base: PathBuf::from("/tree/root"),
+                owners_file: Some(PathBuf::from("/tree/root/foo/OWNERS")),
+                includes: BTreeMap::new(),
+                opaque_submodules: vec![],
+                owners_config: OwnersFileConfig {
+                    all_files: OwnersSet {
+                        inherit: Some(false),
+                        owners: HashSet::from(["grace.hopper".to_string()]),
+                        expiries: BTreeMap::new(),
+                    },
+                    pattern_overrides: HashMap::from([(
+                        "*".to_string(),
+                        OwnersSet {
+                            inherit: Some(false),
+                            owners: HashSet::from(["mary.jackson".to_string()]),
+                            expiries: BTreeMap::new(),
+                        },
+                    )]),
+                },
+                children: vec![],
+            }],
+        };
+
+        let (mut codeowners, mut sources) = generate_codeowners_with_sources(&tree_node, true)?;
+        add_owners_file_rules(&tree_node, &mut codeowners, &mut sources)?;
+        add_unowned_rules([".github/CODEOWNERS"], &mut codeowners);
+
+        // The OWNERS file rule sorts after, and so takes precedence over, the pattern override.
+        let expected = indoc! {"\
+            * @ada.lovelace
+            /.github/CODEOWNERS
+            /OWNERS @ada.lovelace
+            /foo/ @grace.hopper
+            /foo/* @mary.jackson
+            /foo/OWNERS @grace.hopper"
+        };
+        assert_eq!(to_codeowners_string(codeowners), expected);
+
+        // An override sorting after the OWNERS file's rule would still take precedence
+        let mut tree_node = tree_node;
+        tree_node.children[0]
+            .owners_config
+            .pattern_overrides
+            .insert(
+                "[A-Z]*".to_string(),
+                OwnersSet {
+                    inherit: Some(false),
+                    owners: HashSet::from(["katherine.johnson".to_string()]),
+                    expiries: BTreeMap::new(),
+                },
+            );
+        let (mut codeowners, mut sources) = generate_codeowners_with_sources(&tree_node, true)?;
+        let error = add_owners_file_rules(&tree_node, &mut codeowners, &mut sources)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("/foo/[A-Z]* takes precedence over /foo/OWNERS"));
+        Ok(())
+    }
+
+    #[test]
+    fn source_comments_by_owners_file() -> anyhow::Result<()> {
+        let tree_node = TreeNode {
+            path: PathBuf::from("/tree/root"),
+            repo_base: PathBuf::from("/tree/root"),
+            owners_file: Some(PathBuf::from("/tree/root/OWNERS")),
+            includes: BTreeMap::from([
+                (
+                    PathBuf::from("/tree/root/shared/OWNERS"),
+                    PathBuf::from("/tree/root/OWNERS"),
+                ),
+                (
+                    PathBuf::from("/tree/root/teams/a/OWNERS"),
+                    PathBuf::from("/tree/root/shared/OWNERS"),
+                ),
+                (
+                    PathBuf::from("/tree/root/teams/b/OWNERS"),
+                    PathBuf::from("/tree/root/OWNERS"),
+                ),
+            ]),
+            opaque_submodules: vec![],
+            owners_config: OwnersFileConfig {
+                all_files: OwnersSet {
+                    inherit: None,
+                    owners: HashSet::from(["ada.lovelace".to_string()]),
+                    expiries: BTreeMap::new(),
+                },
+                pattern_overrides: HashMap::from([(
+                    "zeta.md".to_string(),
+                    OwnersSet {
+                        inherit: Some(false),
+                        owners: HashSet::from(["mary.jackson".to_string()]),
+                        expiries: BTreeMap::new(),
+                    },
+                )]),
+            },
+            children: vec![TreeNode {
+                path: PathBuf::from("/tree/root/foo"),
+                repo_base: PathBuf::from("/tree/root"),
+                owners_file: Some(PathBuf::from("/tree/root/foo/OWNERS")),
+                includes: BTreeMap::new(),
+                opaque_submodules: vec![],
+                owners_config: OwnersFileConfig {
+                    all_files: OwnersSet {
+                        inherit: Some(false),
+                        owners: HashSet::from(["grace.hopper".to_string()]),
+                        expiries: BTreeMap::new(),
+                    },
+                    pattern_overrides: HashMap::default(),
+                },
+                children: vec![],
+            }],
+        };
+
+        let (mut codeowners, sources) = generate_codeowners_with_sources(&tree_node, true)?;
+        add_unowned_rules(["CODEOWNERS"], &mut codeowners);
+        let comments = source_comments(&tree_node, &sources);
+
+        // The root's rules are split by the rules of /foo/, which sort between them.
+        let expected = indoc! {"\
+            # /OWNERS
+            #   includes /shared/OWNERS
+            #     includes /teams/a/OWNERS
+            #   includes /teams/b/OWNERS
+            * @ada.lovelace
+
+            # Not from an OWNERS file
+            /CODEOWNERS
+
+            # /foo/OWNERS
+            /foo/ @grace.hopper
+
+            # /OWNERS
+            #   includes /shared/OWNERS
+            #     includes /teams/a/OWNERS
+            #   includes /teams/b/OWNERS
+            /zeta.md @mary.jackson"
+        };
+        assert_eq!(
+            to_codeowners_string_with_comments(codeowners, &comments),
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn owner_kinds() {
+        // Teams are the same whether written in OWNERS files or rendered in CODEOWNERS
+        for owner in ["org/docs", "@org/docs"] {
+            assert!(is_team(owner));
+            assert_eq!(to_codeowners_owner(owner), "@org/docs");
+        }
+        for owner in ["ada.lovelace", "@ada.lovelace"] {
+            assert!(!is_team(owner));
+            assert!(!is_email(owner));
+            assert_eq!(to_codeowners_owner(owner), "@ada.lovelace");
+        }
+        assert!(is_email("ada@example.com"));
+        assert!(!is_team("ada/lab@example.com"));
+        assert_eq!(to_codeowners_owner("ada@example.com"), "ada@example.com");
+    }
+
+    #[test]
+    fn codeowners_matcher() -> anyhow::Result<()> {
+        let codeowners = HashMap::from([
+            ("/".to_string(), HashSet::from(["ada.lovelace".to_string()])),
+            (
+                "/*.rs".to_string(),
+                HashSet::from(["margaret.hamilton".to_string()]),
+            ),
+            (
+                "/foo/".to_string(),
+                HashSet::from(["grace.hopper".to_string()]),
+            ),
+            (
+                "/foo/**/*.md".to_string(),
+                HashSet::from(["mary.jackson".to_string()]),
+            ),
+            (
+                "/foo/bar/".to_string(),
+                HashSet::from(["katherine.johnson".to_string()]),
+            ),
+            ("/foo/bar/CODEOWNERS".to_string(), HashSet::default()),
+        ]);
+        let matcher = CodeownersMatcher::new(to_codeowners_rules(&codeowners))?;
+
+        assert_eq!(matcher.owners("README.md"), ["@ada.lovelace"]);
+        assert_eq!(matcher.owners("main.rs"), ["@margaret.hamilton"]);
+        // Anchored patterns only match at the given level.
+        assert_eq!(matcher.owners("baz/main.rs"), ["@ada.lovelace"]);
+        assert_eq!(matcher.owners("foo/main.rs"), ["@grace.hopper"]);
+        assert_eq!(matcher.owners("foo/baz/qux.md"), ["@mary.jackson"]);
+        // The last matching rule wins, even when matching a parent directory.
+        assert_eq!(matcher.owners("foo/bar/README.md"), ["@katherine.johnson"]);
+        assert!(matcher.owners("foo/bar/CODEOWNERS").is_empty());
+        assert!(CodeownersMatcher::new(vec![])?
+            .owners("README.md")
+            .is_empty());
+        Ok(())
+    }
+
     #[test]
     fn to_codeowners_string_multilevel() -> anyhow::Result<()> {
         let codeowners = HashMap::from([
@@ -0,0 +1,309 @@
+use crate::codeowners::{to_codeowners_rules, CodeownersMatcher};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::path::Path;
+
+/// What compaction removed from the CODEOWNERS rules.
+#[derive(PartialEq, Debug, Default)]
+pub struct CompactionReport {
+    pub rules_before: usize,
+    /// Rules with the same owners as the closest rule enclosing them.
+    pub redundant: Vec<String>,
+    /// Pattern overrides folded into a broader sibling override with the same owners.
+    pub merged: Vec<String>,
+}
+
+impl CompactionReport {
+    pub fn rules_after(&self) -> usize {
+        self.rules_before - self.redundant.len() - self.merged.len()
+    }
+}
+
+/// Removes rules which don't change the effective ownership of any file.
+///
+/// A rule is removed when the rule which would otherwise apply to all of its files, either the
+/// closest rule enclosing it or, for a plain path, a glob override in the same directory,
+/// has the same owners, and no rule between the two in the written order could match any of its
+/// files. Overlap between patterns is judged conservatively, so a rule is kept whenever in doubt.
+pub fn compact_codeowners(codeowners: &mut HashMap<String, HashSet<String>>) -> CompactionReport {
+    let mut report = CompactionReport {
+        rules_before: codeowners.len(),
+        ..CompactionReport::default()
+    };
+    // Rules are written in sorted order, with later rules taking precedence.
+    let mut rules = codeowners.drain().collect::<BTreeMap<_, _>>();
+    let mut globs = rules
+        .keys()
+        .filter(|pattern| is_glob(pattern))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let patterns = rules.keys().cloned().collect::<Vec<_>>();
+
+    for pattern in patterns {
+        let Some((fallback, is_sibling)) = fallback_rule(&rules, &globs, &pattern) else {
+            continue;
+        };
+        if rules[fallback] != rules[&pattern]
+            || has_overlap_between(&rules, &globs, fallback, &pattern)
+        {
+            continue;
+        }
+        rules.remove(&pattern);
+        globs.remove(&pattern);
+        if is_sibling {
+            report.merged.push(pattern);
+        } else {
+            report.redundant.push(pattern);
+        }
+    }
+
+    codeowners.extend(rules);
+    report
+}
+
+/// Checks that each of the `files`, relative to the repo root, has the same owners under both
+/// sets of rules.
+pub fn verify_same_ownership<I, P>(
+    before: &HashMap<String, HashSet<String>>,
+    after: &HashMap<String, HashSet<String>>,
+    files: I,
+) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let before = CodeownersMatcher::new(to_codeowners_rules(before))?;
+    let after = CodeownersMatcher::new(to_codeowners_rules(after))?;
+    let mut changed = files
+        .into_iter()
+        .filter(|file| before.owners(file) != after.owners(file))
+        .map(|file| file.as_ref().to_path_buf())
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    changed.sort();
+    let file = &changed[0];
+    Err(anyhow!(
+        "Compaction changed the owners of {} files, e.g., {} from {:?} to {:?}",
+        changed.len(),
+        file.display(),
+        before.owners(file),
+        after.owners(file)
+    ))
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn components(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern.split('/').filter(|component| !component.is_empty())
+}
+
+/// Whether the glob `component` matches the plain `name`. Invalid globs are assumed to match.
+fn glob_matches(component: &str, name: &str) -> bool {
+    glob::Pattern::new(component).map_or(true, |glob| glob.matches(name))
+}
+
+/// Whether the patterns could match any of the same files, assuming they could when unsure.
+fn may_overlap(a: &str, b: &str) -> bool {
+    let mut a = components(a);
+    let mut b = components(b);
+    loop {
+        let (Some(a), Some(b)) = (a.next(), b.next()) else {
+            // One is within the other.
+            return true;
+        };
+        if a == "**" || b == "**" {
+            return true;
+        }
+        let matches = match (is_glob(a), is_glob(b)) {
+            (false, false) => a == b,
+            (true, false) => glob_matches(a, b),
+            (false, true) => glob_matches(b, a),
+            (true, true) => true,
+        };
+        if !matches {
+            return false;
+        }
+    }
+}
+
+/// The rules which would match everything `pattern` does by enclosing it, closest first, i.e., the
+/// paths containing it, or the same path for directory patterns, with and without a trailing slash.
+fn enclosing_rules(pattern: &str) -> impl Iterator<Item = String> + '_ {
+    let trimmed = pattern.trim_end_matches('/');
+    let same_path = (pattern.len() > trimmed.len()).then_some(trimmed.len());
+    let ancestors = trimmed.rmatch_indices('/').map(|(index, _)| index);
+    same_path
+        .into_iter()
+        .chain(ancestors)
+        .flat_map(move |index| {
+            let path = &trimmed[..index];
+            // Later in the written order, so it takes precedence.
+            let directory = format!("{}/", path);
+            let path = (!path.is_empty()).then(|| path.to_string());
+            std::iter::once(directory).chain(path)
+        })
+        .filter(move |rule| rule != pattern)
+}
+
+/// The rule which would apply to all the files matched by `pattern`, were it removed, along with
+/// whether it is a sibling override rather than an enclosing path.
+fn fallback_rule<'a>(
+    rules: &'a BTreeMap<String, HashSet<String>>,
+    globs: &'a BTreeSet<String>,
+    pattern: &str,
+) -> Option<(&'a str, bool)> {
+    let enclosing = enclosing_rules(pattern)
+        .find_map(|rule| rules.get_key_value(&rule))
+        .map(|(rule, _)| (rule.as_str(), false));
+    if is_glob(pattern) {
+        return enclosing;
+    }
+
+    // A single component glob override, e.g. `/foo/*.md`, matches everything a plain path in the
+    // same directory it matches, e.g. `/foo/README.md`, does. It sorts after the directory, so
+    // takes precedence over any enclosing rule.
+    let (parent, name) = pattern.trim_end_matches('/').rsplit_once('/')?;
+    let parent = format!("{}/", parent);
+    globs
+        .range::<str, _>((Bound::Included(parent.as_str()), Bound::Excluded(pattern)))
+        .rev()
+        .filter_map(|glob| Some((glob, glob.strip_prefix(&parent)?)))
+        .find(|(_, component)| !component.contains('/') && glob_matches(component, name))
+        .map(|(glob, _)| (glob.as_str(), true))
+        .or(enclosing)
+}
+
+/// Whether any rule strictly between `fallback` and `pattern` could match any of the same files as
+/// `pattern`.
+fn has_overlap_between(
+    rules: &BTreeMap<String, HashSet<String>>,
+    globs: &BTreeSet<String>,
+    fallback: &str,
+    pattern: &str,
+) -> bool {
+    let between = (Bound::Excluded(fallback), Bound::Excluded(pattern));
+    if is_glob(pattern) {
+        return rules
+            .range::<str, _>(between)
+            .any(|(rule, _)| may_overlap(rule, pattern));
+    }
+    // A plain path can only overlap with glob rules and the rules enclosing it, as anything within
+    // it sorts after it.
+    enclosing_rules(pattern).any(|rule| rule.as_str() > fallback && rules.contains_key(&rule))
+        || globs
+            .range::<str, _>(between)
+            .any(|glob| may_overlap(glob, pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compaction::{compact_codeowners, verify_same_ownership};
+    use std::collections::{HashMap, HashSet};
+
+    fn rules(rules: &[(&str, &[&str])]) -> HashMap<String, HashSet<String>> {
+        rules
+            .iter()
+            .map(|(pattern, owners)| {
+                let owners = owners.iter().map(|owner| owner.to_string()).collect();
+                (pattern.to_string(), owners)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn removes_redundant_rules() -> anyhow::Result<()> {
+        let before = rules(&[
+            ("/", &["ada.lovelace"]),
+            ("/foo/", &["ada.lovelace"]),
+            ("/foo/*.md", &["ada.lovelace"]),
+            ("/foo/bar/", &["grace.hopper"]),
+            ("/foo/bar/baz/", &["ada.lovelace"]),
+            ("/foo/bar/qux/", &["grace.hopper"]),
+            ("/qux/", &["mary.jackson"]),
+            // Sorts between /qux/ and its subdirectories, and could match files in them
+            ("/qux/**/*.rs", &["katherine.johnson"]),
+            ("/qux/quux/", &["mary.jackson"]),
+            ("/qux/zap/", &["mary.jackson"]),
+            ("/vendor/lib", &["mary.jackson"]),
+            ("/vendor/lib/nested/", &["mary.jackson"]),
+        ]);
+        let mut after = before.clone();
+        let report = compact_codeowners(&mut after);
+
+        assert_eq!(
+            report.redundant,
+            vec!["/foo/", "/foo/*.md", "/foo/bar/qux/", "/vendor/lib/nested/"]
+        );
+        assert!(report.merged.is_empty());
+        assert_eq!(report.rules_after(), 8);
+        assert_eq!(
+            after,
+            rules(&[
+                ("/", &["ada.lovelace"]),
+                ("/foo/bar/", &["grace.hopper"]),
+                ("/foo/bar/baz/", &["ada.lovelace"]),
+                ("/qux/", &["mary.jackson"]),
+                ("/qux/**/*.rs", &["katherine.johnson"]),
+                ("/qux/quux/", &["mary.jackson"]),
+                ("/qux/zap/", &["mary.jackson"]),
+                ("/vendor/lib", &["mary.jackson"]),
+            ])
+        );
+
+        let files = [
+            "README.md",
+            "foo/a.md",
+            "foo/b.rs",
+            "foo/bar/c.rs",
+            "foo/bar/baz/d.rs",
+            "foo/bar/qux/e.rs",
+            "qux/f.rs",
+            "qux/quux/g.rs",
+            "qux/quux/h.md",
+            "qux/zap/i.rs",
+            "vendor/lib/nested/j.rs",
+        ];
+        verify_same_ownership(&before, &after, files)?;
+        Ok(())
+    }
+
+    #[test]
+    fn merges_sibling_overrides() -> anyhow::Result<()> {
+        let before = rules(&[
+            ("/", &["ada.lovelace"]),
+            ("/docs/", &["grace.hopper"]),
+            ("/docs/*.md", &["mary.jackson"]),
+            ("/docs/README.md", &["mary.jackson"]),
+            // Sorts between *.md and DESIGN.md, and matches it
+            ("/docs/D*", &["katherine.johnson"]),
+            ("/docs/DESIGN.md", &["mary.jackson"]),
+            ("/docs/notes.txt", &["mary.jackson"]),
+        ]);
+        let mut after = before.clone();
+        let report = compact_codeowners(&mut after);
+
+        assert!(report.redundant.is_empty());
+        assert_eq!(report.merged, vec!["/docs/README.md"]);
+        assert!(!after.contains_key("/docs/README.md"));
+        assert!(after.contains_key("/docs/DESIGN.md"));
+
+        let files = [
+            "docs/README.md",
+            "docs/DESIGN.md",
+            "docs/Diagrams.md",
+            "docs/notes.txt",
+            "docs/other.txt",
+        ];
+        verify_same_ownership(&before, &after, files)?;
+
+        // Any change in effective ownership is caught
+        after.remove("/docs/DESIGN.md");
+        assert!(verify_same_ownership(&before, &after, files).is_err());
+        Ok(())
+    }
+}
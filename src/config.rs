@@ -0,0 +1,456 @@
+use crate::expiry::Date;
+use crate::owners_tree::SubmoduleMode;
+use crate::pipeline::OutputTarget;
+use crate::policy::MinOwnersPolicy;
+use crate::teams::TeamMode;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file, discovered at the root of the repository.
+pub const CONFIG_FILE_NAME: &str = ".github-distributed-owners.toml";
+
+const DEFAULT_IMPLICIT_INHERIT: bool = true;
+const DEFAULT_ALLOW_NON_GIT_FILES: bool = false;
+const DEFAULT_OWNERS_FILE_NAME: &str = "OWNERS";
+const DEFAULT_CACHE: bool = true;
+const DEFAULT_PROTECT_OWNERS_FILES: bool = false;
+const DEFAULT_SOURCE_COMMENTS: bool = false;
+const DEFAULT_COMPACT: bool = false;
+/// GitHub ignores CODEOWNERS files of 3 MB or more. Taken as decimal megabytes, to be safe.
+const DEFAULT_SIZE_LIMIT: u64 = 3_000_000;
+const DEFAULT_FAIL_ON_SIZE_LIMIT: bool = false;
+const DEFAULT_EXPIRY_WARNING_DAYS: u32 = 14;
+
+/// Tool settings, which can come from the config file, command line flags, or both.
+///
+/// Unset values fall back to the defaults, see the accessor methods.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub output_file: Option<PathBuf>,
+    pub implicit_inherit: Option<bool>,
+    pub allow_non_git_files: Option<bool>,
+    pub owners_file_names: Option<Vec<String>>,
+    pub message: Option<String>,
+    pub submodules: Option<SubmoduleMode>,
+    pub cache: Option<bool>,
+    /// Whether to add rules giving each OWNERS file to its directory's owners, and leaving the
+    /// generated CODEOWNERS files unowned.
+    pub protect_owners_files: Option<bool>,
+    /// Whether to comment each group of generated rules with the OWNERS file they come from.
+    pub source_comments: Option<bool>,
+    /// Whether to remove rules which don't change the effective ownership of any file.
+    pub compact: Option<bool>,
+    /// Size, in bytes, above which the generated CODEOWNERS file is reported.
+    pub size_limit: Option<u64>,
+    /// Whether exceeding `size_limit` is an error, rather than a warning.
+    pub fail_on_size_limit: Option<bool>,
+    /// Team membership file, used to adjust owners per `team_mode`.
+    pub teams_file: Option<PathBuf>,
+    pub team_mode: Option<TeamMode>,
+    /// Days ahead to warn about temporary owners expiring.
+    pub expiry_warning_days: Option<u32>,
+    /// The date owners expire relative to, rather than today. Only settable on the command line.
+    #[serde(skip)]
+    pub now: Option<Date>,
+    /// Policy file, with rules the resolved ownership must follow.
+    pub policy_file: Option<PathBuf>,
+    /// Baseline of known policy violations, which don't fail generation.
+    pub baseline: Option<PathBuf>,
+    /// Additional output targets, on top of `output_file`.
+    // NB: Tables need to come after plain values when serializing to TOML.
+    pub outputs: Option<Vec<OutputTarget>>,
+    /// Minimum owners required for every rule. Only settable in the config file.
+    pub min_owners: Option<MinOwnersPolicy>,
+}
+
+impl Config {
+    /// Loads the config file at the given path.
+    ///
+    /// Relative paths within the file are resolved relative to the directory containing it.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| {
+            anyhow!("Failed to read config file '{}': {}", path.display(), error)
+        })?;
+        let mut config: Config = toml::from_str(&text).map_err(|error| {
+            anyhow!(
+                "Failed to parse config file '{}': {}",
+                path.display(),
+                error
+            )
+        })?;
+        if let Some(config_dir) = path.parent() {
+            if let Some(output_file) = &config.output_file {
+                config.output_file = Some(config_dir.join(output_file));
+            }
+            if let Some(policy_file) = &config.policy_file {
+                config.policy_file = Some(config_dir.join(policy_file));
+            }
+            if let Some(baseline) = &config.baseline {
+                config.baseline = Some(config_dir.join(baseline));
+            }
+            if let Some(teams_file) = &config.teams_file {
+                config.teams_file = Some(config_dir.join(teams_file));
+            }
+            for output_target in config.outputs.iter_mut().flatten() {
+                if let Some(output_path) = &output_target.path {
+                    output_target.path = Some(config_dir.join(output_path));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the config file from the root of the repository, if there is one.
+    pub fn discover<P: AsRef<Path>>(repo_root: P) -> anyhow::Result<Config> {
+        let path = repo_root.as_ref().join(CONFIG_FILE_NAME);
+        if path.is_file() {
+            Config::from_file(path)
+        } else {
+            Ok(Config::default())
+        }
+    }
+
+    /// Combines two configs, with values set in `overrides` taking precedence.
+    pub fn merge(self, overrides: Config) -> Config {
+        Config {
+            output_file: overrides.output_file.or(self.output_file),
+            implicit_inherit: overrides.implicit_inherit.or(self.implicit_inherit),
+            allow_non_git_files: overrides.allow_non_git_files.or(self.allow_non_git_files),
+            owners_file_names: overrides.owners_file_names.or(self.owners_file_names),
+            message: overrides.message.or(self.message),
+            submodules: overrides.submodules.or(self.submodules),
+            cache: overrides.cache.or(self.cache),
+            protect_owners_files: overrides.protect_owners_files.or(self.protect_owners_files),
+            source_comments: overrides.source_comments.or(self.source_comments),
+            compact: overrides.compact.or(self.compact),
+            size_limit: overrides.size_limit.or(self.size_limit),
+            fail_on_size_limit: overrides.fail_on_size_limit.or(self.fail_on_size_limit),
+            teams_file: overrides.teams_file.or(self.teams_file),
+            team_mode: overrides.team_mode.or(self.team_mode),
+            expiry_warning_days: overrides.expiry_warning_days.or(self.expiry_warning_days),
+            now: overrides.now.or(self.now),
+            policy_file: overrides.policy_file.or(self.policy_file),
+            baseline: overrides.baseline.or(self.baseline),
+            outputs: overrides.outputs.or(self.outputs),
+            min_owners: overrides.min_owners.or(self.min_owners),
+        }
+    }
+
+    /// Returns a copy of the config with defaults filled in for unset values.
+    pub fn with_defaults(&self) -> Config {
+        Config {
+            implicit_inherit: Some(self.implicit_inherit()),
+            allow_non_git_files: Some(self.allow_non_git_files()),
+            owners_file_names: Some(self.owners_file_names()),
+            submodules: Some(self.submodules()),
+            cache: Some(self.cache()),
+            protect_owners_files: Some(self.protect_owners_files()),
+            source_comments: Some(self.source_comments()),
+            compact: Some(self.compact()),
+            size_limit: Some(self.size_limit()),
+            fail_on_size_limit: Some(self.fail_on_size_limit()),
+            team_mode: Some(self.team_mode()),
+            expiry_warning_days: Some(self.expiry_warning_days()),
+            ..self.clone()
+        }
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// All targets to write output to. Defaults to CODEOWNERS format on stdout.
+    pub fn output_targets(&self) -> Vec<OutputTarget> {
+        let mut output_targets = Vec::new();
+        if let Some(output_file) = &self.output_file {
+            output_targets.push(OutputTarget::codeowners(output_file.clone()));
+        }
+        output_targets.extend(self.outputs.iter().flatten().cloned());
+        if output_targets.is_empty() {
+            output_targets.push(OutputTarget::default());
+        }
+        output_targets
+    }
+
+    pub fn implicit_inherit(&self) -> bool {
+        self.implicit_inherit.unwrap_or(DEFAULT_IMPLICIT_INHERIT)
+    }
+
+    pub fn allow_non_git_files(&self) -> bool {
+        self.allow_non_git_files
+            .unwrap_or(DEFAULT_ALLOW_NON_GIT_FILES)
+    }
+
+    pub fn owners_file_names(&self) -> Vec<String> {
+        match &self.owners_file_names {
+            Some(names) if !names.is_empty() => names.clone(),
+            _ => vec![DEFAULT_OWNERS_FILE_NAME.to_string()],
+        }
+    }
+
+    pub fn submodules(&self) -> SubmoduleMode {
+        self.submodules.unwrap_or_default()
+    }
+
+    pub fn cache(&self) -> bool {
+        self.cache.unwrap_or(DEFAULT_CACHE)
+    }
+
+    pub fn protect_owners_files(&self) -> bool {
+        self.protect_owners_files
+            .unwrap_or(DEFAULT_PROTECT_OWNERS_FILES)
+    }
+
+    pub fn source_comments(&self) -> bool {
+        self.source_comments.unwrap_or(DEFAULT_SOURCE_COMMENTS)
+    }
+
+    pub fn compact(&self) -> bool {
+        self.compact.unwrap_or(DEFAULT_COMPACT)
+    }
+
+    pub fn size_limit(&self) -> u64 {
+        self.size_limit.unwrap_or(DEFAULT_SIZE_LIMIT)
+    }
+
+    pub fn fail_on_size_limit(&self) -> bool {
+        self.fail_on_size_limit
+            .unwrap_or(DEFAULT_FAIL_ON_SIZE_LIMIT)
+    }
+
+    pub fn team_mode(&self) -> TeamMode {
+        self.team_mode.unwrap_or_default()
+    }
+
+    pub fn expiry_warning_days(&self) -> u32 {
+        self.expiry_warning_days
+            .unwrap_or(DEFAULT_EXPIRY_WARNING_DAYS)
+    }
+
+    pub fn today(&self) -> Date {
+        self.now.unwrap_or_else(Date::today)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, CONFIG_FILE_NAME};
+    use crate::owners_tree::SubmoduleMode;
+    use crate::pipeline::{OutputFormat, OutputTarget};
+    use crate::policy::MinOwnersPolicy;
+    use crate::teams::TeamMode;
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn discover_config_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            CONFIG_FILE_NAME,
+            indoc! {r#"
+                output-file = ".github/CODEOWNERS"
+                implicit-inherit = false
+                owners-file-names = ["OWNERS", ".owners"]
+                message = "Run `make owners` to regenerate."
+                submodules = "opaque"
+                cache = false
+                protect-owners-files = true
+                source-comments = true
+                compact = true
+                size-limit = 1000000
+                fail-on-size-limit = true
+                teams-file = "teams.toml"
+                team-mode = "expand"
+                expiry-warning-days = 30
+                policy-file = "owners-policy.toml"
+                baseline = "owners-baseline.json"
+
+                [[outputs]]
+                format = "json"
+                path = "owners.json"
+
+                [min-owners]
+                individuals = 2
+                teams = 1
+                exempt = ["/docs/"]
+                "#
+            },
+        )?;
+
+        let expected = Config {
+            output_file: Some(temp_dir.path().join(".github/CODEOWNERS")),
+            implicit_inherit: Some(false),
+            allow_non_git_files: None,
+            owners_file_names: Some(vec!["OWNERS".to_string(), ".owners".to_string()]),
+            message: Some("Run `make owners` to regenerate.".to_string()),
+            submodules: Some(SubmoduleMode::Opaque),
+            cache: Some(false),
+            protect_owners_files: Some(true),
+            source_comments: Some(true),
+            compact: Some(true),
+            size_limit: Some(1_000_000),
+            fail_on_size_limit: Some(true),
+            teams_file: Some(temp_dir.path().join("teams.toml")),
+            team_mode: Some(TeamMode::Expand),
+            expiry_warning_days: Some(30),
+            now: None,
+            policy_file: Some(temp_dir.path().join("owners-policy.toml")),
+            baseline: Some(temp_dir.path().join("owners-baseline.json")),
+            outputs: Some(vec![OutputTarget {
+                format: OutputFormat::Json,
+                path: Some(temp_dir.path().join("owners.json")),
+            }]),
+            min_owners: Some(MinOwnersPolicy {
+                individuals: Some(2),
+                teams: Some(1),
+                exempt: vec!["/docs/".to_string()],
+            }),
+        };
+
+        assert_eq!(Config::discover(temp_dir.path())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn discover_no_config_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        assert_eq!(Config::discover(temp_dir.path())?, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_setting() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(&temp_dir, CONFIG_FILE_NAME, "implicit-inhert = false\n")?;
+        assert!(Config::discover(temp_dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let file_config = Config {
+            output_file: Some(PathBuf::from("/repo/.github/CODEOWNERS")),
+            implicit_inherit: Some(false),
+            message: Some("From the config file".to_string()),
+            ..Config::default()
+        };
+        let flags_config = Config {
+            implicit_inherit: Some(true),
+            allow_non_git_files: Some(true),
+            ..Config::default()
+        };
+
+        let expected = Config {
+            output_file: Some(PathBuf::from("/repo/.github/CODEOWNERS")),
+            implicit_inherit: Some(true),
+            allow_non_git_files: Some(true),
+            owners_file_names: None,
+            message: Some("From the config file".to_string()),
+            submodules: None,
+            cache: None,
+            protect_owners_files: None,
+            source_comments: None,
+            compact: None,
+            size_limit: None,
+            fail_on_size_limit: None,
+            teams_file: None,
+            team_mode: None,
+            expiry_warning_days: None,
+            now: None,
+            policy_file: None,
+            baseline: None,
+            outputs: None,
+            min_owners: None,
+        };
+
+        assert_eq!(file_config.merge(flags_config), expected);
+    }
+
+    #[test]
+    fn with_defaults() {
+        let config = Config {
+            implicit_inherit: Some(false),
+            ..Config::default()
+        };
+
+        let expected = Config {
+            output_file: None,
+            implicit_inherit: Some(false),
+            allow_non_git_files: Some(false),
+            owners_file_names: Some(vec!["OWNERS".to_string()]),
+            message: None,
+            submodules: Some(SubmoduleMode::Ignore),
+            cache: Some(true),
+            protect_owners_files: Some(false),
+            source_comments: Some(false),
+            compact: Some(false),
+            size_limit: Some(3_000_000),
+            fail_on_size_limit: Some(false),
+            teams_file: None,
+            team_mode: Some(TeamMode::Collapse),
+            expiry_warning_days: Some(14),
+            now: None,
+            policy_file: None,
+            baseline: None,
+            outputs: None,
+            min_owners: None,
+        };
+
+        assert_eq!(config.with_defaults(), expected);
+    }
+
+    #[test]
+    fn to_toml() -> anyhow::Result<()> {
+        let config = Config {
+            implicit_inherit: Some(false),
+            owners_file_names: Some(vec!["OWNERS".to_string()]),
+            ..Config::default()
+        };
+
+        let expected = indoc! {r#"
+            implicit-inherit = false
+            owners-file-names = ["OWNERS"]
+            "#
+        };
+
+        assert_eq!(config.to_toml()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn output_targets() {
+        let config = Config {
+            output_file: Some(PathBuf::from(".github/CODEOWNERS")),
+            outputs: Some(vec![
+                OutputTarget::codeowners(PathBuf::from("docs/CODEOWNERS")),
+                OutputTarget {
+                    format: OutputFormat::Json,
+                    path: Some(PathBuf::from("owners.json")),
+                },
+            ]),
+            ..Config::default()
+        };
+
+        let expected = vec![
+            OutputTarget::codeowners(PathBuf::from(".github/CODEOWNERS")),
+            OutputTarget::codeowners(PathBuf::from("docs/CODEOWNERS")),
+            OutputTarget {
+                format: OutputFormat::Json,
+                path: Some(PathBuf::from("owners.json")),
+            },
+        ];
+
+        assert_eq!(config.output_targets(), expected);
+        assert_eq!(
+            Config::default().output_targets(),
+            vec![OutputTarget::default()]
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use crate::owners_set::OwnersSet;
+use crate::owners_tree::{OwnersTree, TreeNode};
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A calendar date, written as `YYYY-MM-DD`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// The current date, in UTC.
+    pub fn today() -> Date {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the Unix epoch")
+            .as_secs();
+        Date::from_days((seconds / 86_400) as i64)
+    }
+
+    // Conversions to and from days since the Unix epoch, per
+    // http://howardhinnant.github.io/date_algorithms.html
+
+    fn from_days(days: i64) -> Date {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date { year, month, day }
+    }
+
+    fn to_days(self) -> i64 {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The number of days from this date until `later`, negative if it's earlier.
+    pub fn days_until(self, later: Date) -> i64 {
+        later.to_days() - self.to_days()
+    }
+}
+
+impl FromStr for Date {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Date> {
+        lazy_static! {
+            static ref RE: Regex =
+                Regex::new(r"^(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})$").unwrap();
+        }
+        let invalid = || anyhow!("Invalid date '{}'. Expected 'YYYY-MM-DD'.", text);
+        let captures = RE.captures(text).ok_or_else(invalid)?;
+        let year = captures["year"].parse()?;
+        let month = captures["month"].parse()?;
+        let day = captures["day"].parse()?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        Ok(Date { year, month, day })
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Date> {
+        text.parse()
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> String {
+        date.to_string()
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A temporary owner, in an OWNERS file or one of its sections.
+#[derive(PartialEq, Debug)]
+pub struct ExpiringOwner {
+    /// The OWNERS file, relative to the root of the tree, e.g., `/foo/OWNERS`.
+    pub owners_file: String,
+    /// The file pattern section, if not the directory level owners.
+    pub section: Option<String>,
+    pub owner: String,
+    pub expires: Date,
+}
+
+impl fmt::Display for ExpiringOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}", self.owner, self.owners_file)?;
+        if let Some(section) = &self.section {
+            write!(f, " [{}]", section)?;
+        }
+        write!(f, ", expiring {}", self.expires)
+    }
+}
+
+/// The `owners_file` relative to the `root` of the tree, e.g., `/foo/OWNERS`.
+fn relative_owners_file(owners_file: &Path, root: &Path) -> String {
+    match owners_file.strip_prefix(root) {
+        Ok(relative_path) => format!("/{}", relative_path.to_string_lossy()),
+        Err(_) => owners_file.to_string_lossy().to_string(),
+    }
+}
+
+/// Visits each owners set in the tree, along with its OWNERS file, relative to the root, and
+/// section.
+fn for_each_owners_set<F>(owners_tree: &OwnersTree, mut visit: F)
+where
+    F: FnMut(&str, Option<&str>, &OwnersSet),
+{
+    let mut nodes: Vec<&TreeNode> = vec![owners_tree];
+    while let Some(node) = nodes.pop() {
+        if let Some(owners_file) = &node.owners_file {
+            let owners_file = relative_owners_file(owners_file, &owners_tree.path);
+            let owners_config = &node.owners_config;
+            visit(&owners_file, None, &owners_config.all_files);
+            for (pattern, owners_set) in &owners_config.pattern_overrides {
+                visit(&owners_file, Some(pattern), owners_set);
+            }
+        }
+        nodes.extend(node.children.iter());
+    }
+}
+
+/// As [for_each_owners_set], for changing the owners sets.
+fn for_each_owners_set_mut<F>(owners_tree: &mut OwnersTree, mut visit: F)
+where
+    F: FnMut(&str, Option<&str>, &mut OwnersSet),
+{
+    let root = owners_tree.path.clone();
+    let mut nodes: Vec<&mut TreeNode> = vec![owners_tree];
+    while let Some(node) = nodes.pop() {
+        if let Some(owners_file) = &node.owners_file {
+            let owners_file = relative_owners_file(owners_file, &root);
+            let owners_config = &mut node.owners_config;
+            visit(&owners_file, None, &mut owners_config.all_files);
+            for (pattern, owners_set) in &mut owners_config.pattern_overrides {
+                visit(&owners_file, Some(pattern), owners_set);
+            }
+        }
+        nodes.extend(node.children.iter_mut());
+    }
+}
+
+/// Removes owners which expired before `today` from the tree, returning them, sorted by OWNERS
+/// file. Owners are still owners on the day they expire.
+pub fn drop_expired_owners(owners_tree: &mut OwnersTree, today: Date) -> Vec<ExpiringOwner> {
+    let mut expired = Vec::new();
+    for_each_owners_set_mut(owners_tree, |owners_file, section, owners_set| {
+        owners_set.expiries.retain(|owner, expires| {
+            if *expires >= today {
+                return true;
+            }
+            owners_set.owners.remove(owner);
+            expired.push(ExpiringOwner {
+                owners_file: owners_file.to_string(),
+                section: section.map(str::to_string),
+                owner: owner.clone(),
+                expires: *expires,
+            });
+            false
+        });
+    });
+    sort(&mut expired);
+    expired
+}
+
+/// Owners expiring within `days` of `today`, sorted by OWNERS file, so they can be renewed or
+/// replaced in time.
+pub fn owners_expiring_within(
+    owners_tree: &OwnersTree,
+    today: Date,
+    days: u32,
+) -> Vec<ExpiringOwner> {
+    let mut expiring = Vec::new();
+    for_each_owners_set(owners_tree, |owners_file, section, owners_set| {
+        for (owner, expires) in &owners_set.expiries {
+            if (0..=i64::from(days)).contains(&today.days_until(*expires)) {
+                expiring.push(ExpiringOwner {
+                    owners_file: owners_file.to_string(),
+                    section: section.map(str::to_string),
+                    owner: owner.clone(),
+                    expires: *expires,
+                });
+            }
+        }
+    });
+    sort(&mut expiring);
+    expiring
+}
+
+fn sort(owners: &mut [ExpiringOwner]) {
+    owners.sort_by(|a, b| {
+        (&a.owners_file, &a.section, &a.owner).cmp(&(&b.owners_file, &b.section, &b.owner))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allow_filter::FilterGitMetadata;
+    use crate::expiry::{drop_expired_owners, owners_expiring_within, Date, ExpiringOwner};
+    use crate::file_source::WorkingTree;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use std::collections::HashSet;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parse_date() -> anyhow::Result<()> {
+        let date: Date = "2024-02-29".parse()?;
+        assert_eq!(date.to_string(), "2024-02-29");
+        assert_eq!(date.days_until("2024-03-01".parse()?), 1);
+        assert_eq!(date.days_until("2025-02-28".parse()?), 365);
+        assert_eq!(date.days_until("2024-02-01".parse()?), -28);
+        assert_eq!(Date::from_days(date.to_days()), date);
+        assert_eq!(Date::from_days(0).to_string(), "1970-01-01");
+
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("2024-13-01".parse::<Date>().is_err());
+        assert!("2024-1-01".parse::<Date>().is_err());
+        assert!("tomorrow".parse::<Date>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn expired_owners() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {"\
+                ada.lovelace
+                grace.hopper expires=2026-06-30
+                [*.md]
+                mary.jackson expires=2026-07-10
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "foo/OWNERS",
+            indoc! {"\
+                katherine.johnson expires=2026-07-01
+                # Listed without an expiry elsewhere, so never expires
+                ada.lovelace expires=2026-01-01
+                include /shared/OWNERS
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "shared/OWNERS", "ada.lovelace\n")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let mut tree = OwnersTree::load_from_files(
+            root,
+            &LoadOptions {
+                allow_filter: &FilterGitMetadata {},
+                owners_file_names: &["OWNERS"],
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &WorkingTree {},
+                cache: None,
+            },
+        )?;
+        let today = "2026-07-01".parse()?;
+
+        let expiring = owners_expiring_within(&tree, today, 14);
+        assert_eq!(
+            expiring,
+            vec![
+                ExpiringOwner {
+                    owners_file: "/OWNERS".to_string(),
+                    section: Some("*.md".to_string()),
+                    owner: "mary.jackson".to_string(),
+                    expires: "2026-07-10".parse()?,
+                },
+                ExpiringOwner {
+                    owners_file: "/foo/OWNERS".to_string(),
+                    section: None,
+                    owner: "katherine.johnson".to_string(),
+                    expires: "2026-07-01".parse()?,
+                },
+            ]
+        );
+        assert_eq!(
+            expiring[0].to_string(),
+            "mary.jackson in /OWNERS [*.md], expiring 2026-07-10"
+        );
+
+        let expired = drop_expired_owners(&mut tree, today);
+        assert_eq!(
+            expired,
+            vec![ExpiringOwner {
+                owners_file: "/OWNERS".to_string(),
+                section: None,
+                owner: "grace.hopper".to_string(),
+                expires: "2026-06-30".parse()?,
+            }]
+        );
+        assert_eq!(
+            tree.owners_config.all_files.owners,
+            HashSet::from(["ada.lovelace".to_string()])
+        );
+        let foo = &tree.children[0];
+        assert_eq!(
+            foo.owners_config.all_files.owners,
+            HashSet::from(["ada.lovelace".to_string(), "katherine.johnson".to_string()])
+        );
+
+        // Owners are dropped the day after they expire
+        let expired = drop_expired_owners(&mut tree, "2026-07-02".parse()?);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].owner, "katherine.johnson");
+        Ok(())
+    }
+}
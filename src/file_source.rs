@@ -0,0 +1,562 @@
+use crate::git;
+use anyhow::anyhow;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use glob::{MatchOptions, Pattern};
+use log::debug;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// File mode for symlinks in git trees.
+const SYMLINK_MODE: i32 = 0o120000;
+
+/// Where OWNERS files, and the files they include, are read from. Paths are absolute.
+// NB: Sync, as the file tree is walked in parallel.
+pub trait FileSource: Sync {
+    fn read_to_string(&self, path: &Path) -> anyhow::Result<String>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// The subdirectories of `directory`, with any symlinks resolved.
+    fn subdirectories(&self, directory: &Path) -> anyhow::Result<Vec<PathBuf>>;
+
+    /// Resolves `path` to an absolute path without `.`, `..` or symlinks, failing if it doesn't
+    /// exist.
+    fn canonicalize(&self, path: &Path) -> anyhow::Result<PathBuf>;
+
+    /// The files matching the absolute glob `pattern`, in any order.
+    fn glob(&self, pattern: &str) -> anyhow::Result<Vec<PathBuf>>;
+
+    /// The submodules registered with git in the repository containing `repo_root`, whether or
+    /// not they are checked out.
+    fn submodule_paths(&self, repo_root: &Path) -> anyhow::Result<HashSet<PathBuf>>;
+
+    /// All files tracked under `repo_root`, including those within submodules with
+    /// `recurse_submodules`, where they're available.
+    fn tracked_files(
+        &self,
+        repo_root: &Path,
+        recurse_submodules: bool,
+    ) -> anyhow::Result<HashSet<PathBuf>>;
+
+    /// Writes `contents` to the file at `path`, creating any missing parent directories.
+    fn write(&self, path: &Path, contents: &str) -> anyhow::Result<()>;
+}
+
+/// Reads files from the file system, i.e., the working tree.
+#[derive(Debug)]
+pub struct WorkingTree {}
+
+impl FileSource for WorkingTree {
+    fn read_to_string(&self, path: &Path) -> anyhow::Result<String> {
+        Ok(fs::read_to_string(path)?)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn subdirectories(&self, directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut subdirectories = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                // Canonical directories only need symlinks resolving.
+                subdirectories.push(entry.path());
+            } else if file_type.is_symlink() && entry.path().is_dir() {
+                subdirectories.push(entry.path().canonicalize()?);
+            }
+        }
+        Ok(subdirectories)
+    }
+
+    fn canonicalize(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        Ok(path.canonicalize()?)
+    }
+
+    fn glob(&self, pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in glob::glob(pattern)? {
+            let path = entry?;
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn submodule_paths(&self, repo_root: &Path) -> anyhow::Result<HashSet<PathBuf>> {
+        git::submodule_paths(repo_root)
+    }
+
+    fn tracked_files(
+        &self,
+        repo_root: &Path,
+        recurse_submodules: bool,
+    ) -> anyhow::Result<HashSet<PathBuf>> {
+        git::tracked_files(repo_root, recurse_submodules)
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> anyhow::Result<()> {
+        if let Some(parent_dir) = path.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        Ok(fs::write(path, contents)?)
+    }
+}
+
+/// Reads files from the tree of a git revision, without checking it out.
+///
+/// Paths are under the repository's working directory, as though the revision were checked out.
+/// Submodules are never checked out, and symlinks are skipped.
+pub struct GitTree {
+    root: PathBuf,
+    revision: String,
+    // NB: Repository isn't Sync, so blobs are read one at a time.
+    repo: Mutex<Repository>,
+    files: BTreeMap<PathBuf, Oid>,
+    /// Each directory's subdirectories, including submodules.
+    directories: HashMap<PathBuf, Vec<PathBuf>>,
+    submodules: HashSet<PathBuf>,
+}
+
+impl GitTree {
+    /// Reads the tree of `revision`, e.g., a commit, branch or tag, in the repository containing
+    /// `repo_root`.
+    pub fn new<P: AsRef<Path>>(repo_root: P, revision: &str) -> anyhow::Result<GitTree> {
+        let repo = git::open_repository(repo_root)?;
+        let tree = repo
+            .revparse_single(revision)
+            .and_then(|object| object.peel_to_tree())
+            .map_err(|error| {
+                anyhow!(
+                    "Failed to find revision '{}': {}",
+                    revision,
+                    error.message()
+                )
+            })?;
+        let root = repo.workdir().unwrap_or(repo.path()).canonicalize()?;
+
+        let mut files = BTreeMap::new();
+        let mut directories = HashMap::from([(root.clone(), Vec::new())]);
+        let mut submodules = HashSet::new();
+        tree.walk(TreeWalkMode::PreOrder, |directory, entry| {
+            let parent = root.join(directory);
+            let path = parent.join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    directories.entry(parent).or_default().push(path.clone());
+                    directories.entry(path).or_default();
+                }
+                Some(ObjectType::Commit) => {
+                    directories.entry(parent).or_default().push(path.clone());
+                    submodules.insert(path);
+                }
+                Some(ObjectType::Blob) if entry.filemode() != SYMLINK_MODE => {
+                    files.insert(path, entry.id());
+                }
+                _ => debug!("Skipping {:?} at {}", path, revision),
+            }
+            TreeWalkResult::Ok
+        })?;
+        drop(tree);
+
+        Ok(GitTree {
+            root,
+            revision: revision.to_string(),
+            repo: Mutex::new(repo),
+            files,
+            directories,
+            submodules,
+        })
+    }
+
+    /// All files in the tree, in sorted order.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.keys()
+    }
+}
+
+impl FileSource for GitTree {
+    fn read_to_string(&self, path: &Path) -> anyhow::Result<String> {
+        let oid = self.files.get(path).ok_or_else(|| {
+            anyhow!(
+                "'{}' does not exist at revision {}",
+                path.display(),
+                self.revision
+            )
+        })?;
+        let repo = self.repo.lock().unwrap();
+        let blob = repo.find_blob(*oid)?;
+        Ok(String::from_utf8(blob.content().to_vec())?)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+            || self.directories.contains_key(path)
+            || self.submodules.contains(path)
+    }
+
+    fn subdirectories(&self, directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        Ok(self.directories.get(directory).cloned().unwrap_or_default())
+    }
+
+    fn canonicalize(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let canonical = normalize_path(&self.root, path);
+        if !self.exists(&canonical) {
+            return Err(anyhow!(
+                "'{}' does not exist at revision {}",
+                path.display(),
+                self.revision
+            ));
+        }
+        Ok(canonical)
+    }
+
+    fn glob(&self, pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+        glob_paths(pattern, self.files.keys())
+    }
+
+    fn submodule_paths(&self, _repo_root: &Path) -> anyhow::Result<HashSet<PathBuf>> {
+        Ok(self.submodules.clone())
+    }
+
+    fn tracked_files(
+        &self,
+        repo_root: &Path,
+        _recurse_submodules: bool,
+    ) -> anyhow::Result<HashSet<PathBuf>> {
+        Ok(self
+            .files
+            .keys()
+            .filter(|file| file.starts_with(repo_root))
+            .cloned()
+            .collect())
+    }
+
+    fn write(&self, path: &Path, _contents: &str) -> anyhow::Result<()> {
+        Err(anyhow!(
+            "Can't write '{}' to revision {}",
+            path.display(),
+            self.revision
+        ))
+    }
+}
+
+/// Files held in memory, e.g., to preview unsaved changes to OWNERS files, or for tests.
+///
+/// Directories exist implicitly, whenever there are files in them. There are no submodules or
+/// symlinks.
+#[derive(Default)]
+pub struct InMemoryFiles {
+    files: Mutex<BTreeMap<PathBuf, String>>,
+}
+
+impl InMemoryFiles {
+    /// Holds the given files, by absolute path.
+    pub fn new<I, P, S>(files: I) -> InMemoryFiles
+    where
+        I: IntoIterator<Item = (P, S)>,
+        P: AsRef<Path>,
+        S: AsRef<str>,
+    {
+        InMemoryFiles {
+            files: Mutex::new(
+                files
+                    .into_iter()
+                    .map(|(path, contents)| {
+                        (
+                            normalize_path(Path::new("/"), path.as_ref()),
+                            contents.as_ref().to_string(),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let files = self.files.lock().unwrap();
+        // NB: Paths sort component-wise, so any files in the directory come directly after it.
+        let is_dir = files
+            .range(path.to_path_buf()..)
+            .next()
+            .map_or(false, |(file, _)| file != path && file.starts_with(path));
+        is_dir
+    }
+}
+
+impl FileSource for InMemoryFiles {
+    fn read_to_string(&self, path: &Path) -> anyhow::Result<String> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("'{}' does not exist", path.display()))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    fn subdirectories(&self, directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let files = self.files.lock().unwrap();
+        let mut subdirectories = Vec::new();
+        for file in files
+            .range::<Path, _>((Bound::Excluded(directory), Bound::Unbounded))
+            .map(|(file, _)| file)
+            .take_while(|file| file.starts_with(directory))
+        {
+            let mut components = file.strip_prefix(directory)?.components();
+            // Files directly in the directory have a single component.
+            if let (Some(name), Some(_)) = (components.next(), components.next()) {
+                let subdirectory = directory.join(name);
+                if subdirectories.last() != Some(&subdirectory) {
+                    subdirectories.push(subdirectory);
+                }
+            }
+        }
+        Ok(subdirectories)
+    }
+
+    fn canonicalize(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let canonical = normalize_path(Path::new("/"), path);
+        if !self.exists(&canonical) {
+            return Err(anyhow!("'{}' does not exist", path.display()));
+        }
+        Ok(canonical)
+    }
+
+    fn glob(&self, pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+        glob_paths(pattern, self.files.lock().unwrap().keys())
+    }
+
+    fn submodule_paths(&self, _repo_root: &Path) -> anyhow::Result<HashSet<PathBuf>> {
+        Ok(HashSet::new())
+    }
+
+    fn tracked_files(
+        &self,
+        repo_root: &Path,
+        _recurse_submodules: bool,
+    ) -> anyhow::Result<HashSet<PathBuf>> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|file| file.starts_with(repo_root))
+            .cloned()
+            .collect())
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> anyhow::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(normalize_path(Path::new("/"), path), contents.to_string());
+        Ok(())
+    }
+}
+
+/// Resolves `.` and `..` in `path`, relative to `base` if it isn't absolute, without accessing the
+/// file system.
+fn normalize_path(base: &Path, path: &Path) -> PathBuf {
+    let mut normalized = base.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) => normalized = PathBuf::from(component.as_os_str()),
+            Component::RootDir => normalized.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    normalized
+}
+
+/// The `paths` matching the glob `pattern`.
+fn glob_paths<'a, I>(pattern: &str, paths: I) -> anyhow::Result<Vec<PathBuf>>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    let pattern = Pattern::new(pattern)?;
+    // The same matching as walking the file system, where `*` never crosses directories.
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    Ok(paths
+        .into_iter()
+        .filter(|path| pattern.matches_path_with(path, options))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file_source::{FileSource, GitTree, InMemoryFiles};
+    use crate::test_utils::{add_gitlink, commit_all, commit_index, create_test_file};
+    use git2::{IndexAddOption, Repository};
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn git_tree() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "foo/OWNERS", "grace.hopper\n")?;
+        create_test_file(&temp_dir, "foo/bar/baz.rs", "")?;
+        let mut index = repo.index()?;
+        index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        // Added after the files, as adding everything drops submodules which aren't checked out.
+        add_gitlink(&repo, "vendor/lib")?;
+        let first = commit_index(&repo, "First")?;
+        create_test_file(&temp_dir, "foo/OWNERS", "margaret.hamilton\n")?;
+        commit_all(&repo, "Second")?;
+        // Not committed
+        create_test_file(&temp_dir, "OWNERS", "katherine.johnson\n")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let tree = GitTree::new(&root, &first)?;
+        assert_eq!(
+            tree.files().cloned().collect::<Vec<_>>(),
+            vec![
+                root.join("OWNERS"),
+                root.join("foo/OWNERS"),
+                root.join("foo/bar/baz.rs"),
+            ]
+        );
+        assert_eq!(tree.read_to_string(&root.join("OWNERS"))?, "ada.lovelace\n");
+        assert_eq!(
+            tree.read_to_string(&root.join("foo/OWNERS"))?,
+            "grace.hopper\n"
+        );
+        assert!(tree.read_to_string(&root.join("missing")).is_err());
+        assert!(tree.is_file(&root.join("foo/OWNERS")));
+        assert!(!tree.is_file(&root.join("foo")));
+        assert!(tree.exists(&root.join("foo")));
+
+        let mut subdirectories = tree.subdirectories(&root)?;
+        subdirectories.sort();
+        assert_eq!(subdirectories, vec![root.join("foo"), root.join("vendor")]);
+        assert_eq!(
+            tree.submodule_paths(&root)?,
+            [root.join("vendor/lib")].into_iter().collect()
+        );
+        assert_eq!(
+            tree.tracked_files(&root.join("foo"), false)?,
+            [root.join("foo/OWNERS"), root.join("foo/bar/baz.rs")]
+                .into_iter()
+                .collect()
+        );
+
+        assert_eq!(
+            tree.canonicalize(&root.join("foo/bar/../OWNERS"))?,
+            root.join("foo/OWNERS")
+        );
+        assert!(tree.canonicalize(&root.join("foo/missing")).is_err());
+
+        let pattern = format!("{}/*/OWNERS", root.display());
+        assert_eq!(tree.glob(&pattern)?, vec![root.join("foo/OWNERS")]);
+        assert_eq!(
+            tree.glob(&format!("{}/**/*.rs", root.display()))?,
+            vec![root.join("foo/bar/baz.rs")]
+        );
+
+        assert!(GitTree::new(&root, "no-such-branch").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn git_tree_relative_path() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        commit_all(&repo, "First")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let tree = GitTree::new(&root, "HEAD")?;
+        assert_eq!(tree.canonicalize(Path::new("OWNERS"))?, root.join("OWNERS"));
+        assert_eq!(tree.canonicalize(Path::new("."))?, PathBuf::from(&root));
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory_files() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/OWNERS", "grace.hopper\n"),
+            ("/repo/foo/bar/baz.rs", ""),
+            ("/repo/foo-bar/OWNERS", "margaret.hamilton\n"),
+            ("/repo/qux/../README.md", ""),
+        ]);
+        let root = Path::new("/repo");
+
+        assert_eq!(
+            files.read_to_string(&root.join("foo/OWNERS"))?,
+            "grace.hopper\n"
+        );
+        assert!(files.read_to_string(&root.join("missing")).is_err());
+        assert!(files.is_file(&root.join("README.md")));
+        assert!(!files.is_file(&root.join("foo")));
+        assert!(files.exists(&root.join("foo")));
+        assert!(files.exists(root));
+        assert!(!files.exists(&root.join("fo")));
+        assert_eq!(
+            files.subdirectories(root)?,
+            vec![root.join("foo"), root.join("foo-bar")]
+        );
+        assert_eq!(
+            files.subdirectories(&root.join("foo/bar"))?,
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            files.canonicalize(&root.join("foo/bar/../OWNERS"))?,
+            root.join("foo/OWNERS")
+        );
+        assert!(files.canonicalize(&root.join("missing")).is_err());
+        assert_eq!(
+            files.tracked_files(&root.join("foo"), false)?,
+            [root.join("foo/OWNERS"), root.join("foo/bar/baz.rs")]
+                .into_iter()
+                .collect()
+        );
+        let mut matches = files.glob("/repo/*/OWNERS")?;
+        matches.sort();
+        assert_eq!(
+            matches,
+            vec![root.join("foo/OWNERS"), root.join("foo-bar/OWNERS")]
+        );
+
+        files.write(&root.join("new/CODEOWNERS"), "* @ada.lovelace\n")?;
+        assert_eq!(
+            files.read_to_string(&root.join("new/CODEOWNERS"))?,
+            "* @ada.lovelace\n"
+        );
+        assert!(files.exists(&root.join("new")));
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::anyhow;
+use git2::{IndexEntryExtendedFlag, Repository};
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// File mode for submodule entries in the git index.
+const GITLINK_MODE: u32 = 0o160000;
+/// File mode for sparse directory entries in the git index, when using a sparse index.
+const SPARSE_DIRECTORY_MODE: u32 = 0o040000;
+
+/// Opens the git repository containing `path`, which may be the repo root or any directory in it.
+pub fn open_repository<P: AsRef<Path>>(path: P) -> anyhow::Result<Repository> {
+    Repository::discover(path.as_ref()).map_err(|error| {
+        anyhow!(
+            "Failed to open git repository at '{}': {}",
+            path.as_ref().display(),
+            error.message()
+        )
+    })
+}
+
+/// Gathers the files tracked by git which are present in the working tree, as absolute paths.
+///
+/// This reads the git index directly, rather than relying on a git binary, and is relative to the
+/// repository containing `repo_root` regardless of the current working directory. When
+/// `recurse_submodules` is set, files tracked by checked out submodules are included as well.
+pub fn tracked_files<P: AsRef<Path>>(
+    repo_root: P,
+    recurse_submodules: bool,
+) -> anyhow::Result<HashSet<PathBuf>> {
+    let repo = open_repository(repo_root)?;
+    let mut tracked_files = HashSet::new();
+    add_tracked_files(&repo, recurse_submodules, &mut tracked_files)?;
+    Ok(tracked_files)
+}
+
+fn add_tracked_files(
+    repo: &Repository,
+    recurse_submodules: bool,
+    tracked_files: &mut HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let workdir = workdir(repo)?;
+    let index = repo.index()?;
+
+    for entry in index.iter() {
+        let path = workdir.join(String::from_utf8_lossy(&entry.path).as_ref());
+        if entry.mode == GITLINK_MODE && recurse_submodules {
+            match Repository::open(&path) {
+                Ok(submodule) => add_tracked_files(&submodule, recurse_submodules, tracked_files)?,
+                Err(_) => debug!("Ignoring submodule {:?}, which is not checked out", path),
+            }
+            continue;
+        }
+        if entry.mode == GITLINK_MODE || entry.mode == SPARSE_DIRECTORY_MODE {
+            debug!("Ignoring non-file git index entry {:?}", path);
+            continue;
+        }
+        if IndexEntryExtendedFlag::from_bits_truncate(entry.flags_extended).is_skip_worktree() {
+            // Not checked out, e.g., outside the cone of a sparse checkout.
+            debug!("Ignoring {:?}, which is not in the working tree", path);
+            continue;
+        }
+        // If an OWNERS file has been deleted, but the deletion has not yet been staged,
+        // an error would be thrown without filtering them out.
+        if !path.exists() {
+            warn!(
+                "Missing expected git file at `{}`, possibly the deletion has not been staged?",
+                path.display()
+            );
+            continue;
+        }
+        // NB: Entries may appear more than once, at different stages, during a merge conflict.
+        tracked_files.insert(path);
+    }
+    Ok(())
+}
+
+/// Gathers the paths of the submodules registered in the git index, as absolute paths, along with
+/// those registered in any checked out submodules.
+///
+/// Submodules are included whether or not they are checked out.
+pub fn submodule_paths<P: AsRef<Path>>(repo_root: P) -> anyhow::Result<HashSet<PathBuf>> {
+    let repo = open_repository(repo_root)?;
+    let mut submodule_paths = HashSet::new();
+    add_submodule_paths(&repo, &mut submodule_paths)?;
+    Ok(submodule_paths)
+}
+
+fn add_submodule_paths(
+    repo: &Repository,
+    submodule_paths: &mut HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let workdir = workdir(repo)?;
+    for entry in repo.index()?.iter() {
+        if entry.mode != GITLINK_MODE {
+            continue;
+        }
+        let path = workdir.join(String::from_utf8_lossy(&entry.path).as_ref());
+        if let Ok(submodule) = Repository::open(&path) {
+            add_submodule_paths(&submodule, submodule_paths)?;
+        }
+        submodule_paths.insert(path);
+    }
+    Ok(())
+}
+
+fn workdir(repo: &Repository) -> anyhow::Result<&Path> {
+    repo.workdir()
+        .ok_or_else(|| anyhow!("Git repository '{}' is bare.", repo.path().display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::{submodule_paths, tracked_files};
+    use crate::test_utils::{add_gitlink, create_test_file};
+    use git2::{IndexEntryExtendedFlag, IndexEntryFlag, Repository};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    #[test]
+    fn tracked_files_from_index() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "foo/OWNERS", "grace.hopper\n")?;
+        create_test_file(&temp_dir, "foo/untracked/OWNERS", "alan.turing\n")?;
+        create_test_file(&temp_dir, "sparse/OWNERS", "mary.jackson\n")?;
+        create_test_file(&temp_dir, "deleted/OWNERS", "katherine.johnson\n")?;
+
+        let mut index = repo.index()?;
+        index.add_path(Path::new("OWNERS"))?;
+        index.add_path(Path::new("foo/OWNERS"))?;
+        index.add_path(Path::new("deleted/OWNERS"))?;
+        index.add_path(Path::new("sparse/OWNERS"))?;
+        let mut sparse_entry = index.get_path(Path::new("sparse/OWNERS"), 0).unwrap();
+        sparse_entry.flags |= IndexEntryFlag::EXTENDED.bits();
+        sparse_entry.flags_extended |= IndexEntryExtendedFlag::SKIP_WORKTREE.bits();
+        index.add(&sparse_entry)?;
+        index.write()?;
+
+        // Unstaged deletion
+        fs::remove_file(temp_dir.path().join("deleted/OWNERS"))?;
+
+        let workdir = repo.workdir().unwrap();
+        let expected = [workdir.join("OWNERS"), workdir.join("foo/OWNERS")]
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        assert_eq!(tracked_files(temp_dir.path(), false)?, expected);
+        // Works the same from a subdirectory of the repository.
+        assert_eq!(tracked_files(temp_dir.path().join("foo"), false)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn tracked_files_not_a_repository() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        assert!(tracked_files(temp_dir.path(), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tracked_files_with_submodules() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "sub/OWNERS", "grace.hopper\n")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("OWNERS"))?;
+        index.write()?;
+
+        let submodule = Repository::init(temp_dir.path().join("sub"))?;
+        let mut submodule_index = submodule.index()?;
+        submodule_index.add_path(Path::new("OWNERS"))?;
+        submodule_index.write()?;
+        // Registered in the checked out submodule, not the superproject
+        add_gitlink(&submodule, "nested")?;
+        add_gitlink(&repo, "sub")?;
+        // Not checked out
+        add_gitlink(&repo, "missing")?;
+
+        let workdir = repo.workdir().unwrap();
+        assert_eq!(
+            tracked_files(temp_dir.path(), false)?,
+            [workdir.join("OWNERS")].into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            tracked_files(temp_dir.path(), true)?,
+            [workdir.join("OWNERS"), workdir.join("sub/OWNERS")]
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+        assert_eq!(
+            submodule_paths(temp_dir.path())?,
+            [
+                workdir.join("missing"),
+                workdir.join("sub"),
+                workdir.join("sub/nested")
+            ]
+            .into_iter()
+            .collect::<HashSet<_>>()
+        );
+        Ok(())
+    }
+}
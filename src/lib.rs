@@ -0,0 +1,25 @@
+//! Generates GitHub compatible CODEOWNERS files from OWNERS files distributed through the file tree.
+//!
+//! Files are read through a [file_source::FileSource], so OWNERS files can be loaded from the
+//! working tree, a git revision, or memory.
+
+pub mod allow_filter;
+pub mod baseline;
+pub mod cache;
+pub mod codeowners;
+pub mod compaction;
+pub mod config;
+pub mod expiry;
+pub mod file_source;
+pub mod git;
+pub mod owners_file;
+pub mod owners_set;
+pub mod owners_tree;
+pub mod ownership_diff;
+pub mod pipeline;
+pub mod policy;
+pub mod stats;
+pub mod teams;
+
+#[cfg(test)]
+mod test_utils;
@@ -1,19 +1,24 @@
-use crate::allow_filter::{AllowFilter, AllowList, FilterGitMetadata};
-use clap::Parser;
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
-use std::path::PathBuf;
+use github_distributed_owners::allow_filter::{
+    AllowFilter, AllowList, FilterIgnored, IGNORE_FILE_NAME,
+};
+use github_distributed_owners::cache::ParseCache;
+use github_distributed_owners::config::Config;
+use github_distributed_owners::expiry::Date;
+use github_distributed_owners::file_source::{FileSource, GitTree, WorkingTree};
+use github_distributed_owners::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+use github_distributed_owners::ownership_diff::FileOwners;
+use github_distributed_owners::pipeline::OutputTarget;
+use github_distributed_owners::teams::TeamMode;
+use github_distributed_owners::{codeowners, git, ownership_diff, pipeline, stats};
+use log::warn;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-mod codeowners;
-mod owners_file;
-mod owners_set;
-mod owners_tree;
-mod pipeline;
-
-mod allow_filter;
-#[cfg(test)]
-mod test_utils;
-
-const DEFAULT_IMPLICIT_INHERIT: bool = true;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -28,15 +33,104 @@ struct Args {
     #[clap(short, long)]
     output_file: Option<PathBuf>,
 
+    /// Additional output, in the format `<format>[:<path>]`, where format is `codeowners` or
+    /// `json`. Writes to stdout if no path is given. May be given multiple times.
+    #[clap(long = "output", multiple_occurrences(true))]
+    outputs: Vec<OutputTarget>,
+
+    /// Config file to read settings from. Command line flags take precedence over its values.
+    /// Default: .github-distributed-owners.toml in the repo root, if present.
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+
     /// Whether to inherit owners when inheritance is not specified. Default: true.
     #[clap(short, long, parse(try_from_str))]
     // NB: Option<bool> allows for --implicit-inherit [true|false]
     implicit_inherit: Option<bool>,
 
-    /// Don't filter out files which are not managed by git.
+    /// Don't filter out files which are not managed by git. Files ignored by git are still
+    /// filtered out.
     #[clap(long)]
     allow_non_git_files: bool,
 
+    /// Accepted name for OWNERS files. May be given multiple times, in order of precedence for
+    /// when more than one is present in a directory. Default: OWNERS.
+    #[clap(long = "owners-file-name", multiple_occurrences(true))]
+    owners_file_names: Vec<String>,
+
+    /// How to handle git submodules: `ignore` them, treat each as an `opaque` path owned by the
+    /// enclosing directory's owners, or `recurse` into their own OWNERS files. Default: ignore.
+    #[clap(long)]
+    submodules: Option<SubmoduleMode>,
+
+    /// Don't use the cache of parsed OWNERS files, stored in the git directory.
+    #[clap(long)]
+    no_cache: bool,
+
+    /// Add rules giving each OWNERS file to the owners of its directory, and leaving the generated
+    /// CODEOWNERS files without owners, so they don't need adding to OWNERS files by hand.
+    #[clap(long)]
+    protect_owners_files: bool,
+
+    /// Remove rules which don't change the effective ownership of any file, e.g., subdirectories
+    /// with the same owners as their parent, checking the owners of every tracked file are
+    /// unchanged.
+    #[clap(long)]
+    compact: bool,
+
+    /// Size, in bytes, above which the generated CODEOWNERS file is warned about.
+    /// Default: 3000000, as GitHub ignores CODEOWNERS files of 3 MB or more.
+    #[clap(long)]
+    size_limit: Option<u64>,
+
+    /// Fail, rather than warn, when the generated CODEOWNERS file exceeds the size limit.
+    #[clap(long)]
+    fail_on_size_limit: bool,
+
+    /// Team membership file, mapping teams to their members, used to adjust owners per
+    /// --team-mode.
+    #[clap(long)]
+    teams_file: Option<PathBuf>,
+
+    /// With --teams-file, how owners are adjusted: `collapse` individuals already covered by a team
+    /// owning the same rule, or `expand` teams to their members, for hosts without teams.
+    /// Default: collapse.
+    #[clap(long)]
+    team_mode: Option<TeamMode>,
+
+    /// The date owners expire relative to, as YYYY-MM-DD, rather than today, for reproducible
+    /// output.
+    #[clap(long)]
+    now: Option<Date>,
+
+    /// Days ahead to warn about temporary owners expiring. Default: 14.
+    #[clap(long)]
+    expiry_warning_days: Option<u32>,
+
+    /// Comment each group of rules in the generated CODEOWNERS with the OWNERS file they come
+    /// from, and the files it includes.
+    #[clap(long)]
+    source_comments: bool,
+
+    /// Policy file, with rules the resolved ownership must follow. Generation fails on any
+    /// violations which aren't allowed by it.
+    #[clap(long)]
+    policy_file: Option<PathBuf>,
+
+    /// Baseline of known policy violations, which don't fail generation, so only new violations
+    /// do.
+    #[clap(long)]
+    baseline: Option<PathBuf>,
+
+    /// Write all current policy violations to the baseline, rather than failing on them.
+    #[clap(long)]
+    write_baseline: bool,
+
+    /// Generate from the files at a git revision, e.g., a commit or branch, instead of the working
+    /// tree, without checking it out.
+    #[clap(long)]
+    rev: Option<String>,
+
     /// Add custom message to the auto-generated header/footer.
     ///
     /// This can be useful if you want to provide context for your specific project,
@@ -46,29 +140,451 @@ struct Args {
 
     #[clap(flatten)]
     verbose: Verbosity,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect the tool configuration.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Compare the effective owners of each file tracked at both of two git revisions, e.g., to
+    /// review a change to OWNERS files, and list files tracked at only one of them, with their
+    /// owners. Files are read from git, not the working tree.
+    Diff(DiffArgs),
+    /// Summarise how ownership is distributed, e.g., owners per directory and files per owner,
+    /// over the files tracked by git.
+    Stats(StatsArgs),
+    /// Regenerate whenever OWNERS files, or files they include, change, printing the changes to
+    /// CODEOWNERS. Output is only written to files, not stdout.
+    Watch {
+        /// How long to wait for further changes before regenerating, in milliseconds.
+        #[clap(long, default_value = "300")]
+        debounce_ms: u64,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Revision to compare from, e.g., a commit or branch.
+    rev_a: String,
+
+    /// Revision to compare to.
+    rev_b: String,
+
+    /// List each changed file, rather than collapsing files with the same change into their
+    /// directory.
+    #[clap(long)]
+    per_file: bool,
+
+    /// Only report owners which were removed, including those of removed files, as moved files
+    /// can't be told apart from removed ones.
+    #[clap(long)]
+    only_lost_owners: bool,
+
+    /// Exit with an error if any changes are reported.
+    #[clap(long)]
+    exit_code: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// Print the stats as JSON, with every entry, rather than as text.
+    #[clap(long)]
+    json: bool,
+
+    /// How many of the owners with the most files, and most included files, to list as text.
+    #[clap(long, default_value = "10")]
+    top: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration, after merging the config file and command line flags.
+    Show,
+}
+
+impl Args {
+    /// Settings given as command line flags, which take precedence over the config file.
+    fn to_config(&self) -> Config {
+        Config {
+            output_file: self.output_file.clone(),
+            implicit_inherit: self.implicit_inherit,
+            // NB: The flag can only enable this, so leave it to the config file otherwise.
+            allow_non_git_files: self.allow_non_git_files.then_some(true),
+            owners_file_names: if self.owners_file_names.is_empty() {
+                None
+            } else {
+                Some(self.owners_file_names.clone())
+            },
+            message: self.message.clone(),
+            submodules: self.submodules,
+            // NB: The flag can only disable this, so leave it to the config file otherwise.
+            cache: self.no_cache.then_some(false),
+            // NB: The flag can only enable this, so leave it to the config file otherwise.
+            protect_owners_files: self.protect_owners_files.then_some(true),
+            source_comments: self.source_comments.then_some(true),
+            compact: self.compact.then_some(true),
+            size_limit: self.size_limit,
+            fail_on_size_limit: self.fail_on_size_limit.then_some(true),
+            teams_file: self.teams_file.clone(),
+            team_mode: self.team_mode,
+            expiry_warning_days: self.expiry_warning_days,
+            now: self.now,
+            policy_file: self.policy_file.clone(),
+            baseline: self.baseline.clone(),
+            outputs: if self.outputs.is_empty() {
+                None
+            } else {
+                Some(self.outputs.clone())
+            },
+            // NB: The policy, with its exemptions, is only set in the config file.
+            min_owners: None,
+        }
+    }
 }
 
-fn run_pipeline<F: AllowFilter>(args: Args, allow_filter: &F) -> anyhow::Result<()> {
+/// The filter for which OWNERS files to use, per the config.
+fn allow_filter(repo_root: &Path, config: &Config) -> anyhow::Result<Box<dyn AllowFilter>> {
+    if config.allow_non_git_files() {
+        Ok(Box::new(FilterIgnored::new(repo_root)?))
+    } else {
+        Ok(Box::new(AllowList::allow_git_files(
+            repo_root,
+            &config.owners_file_names(),
+            config.submodules() == SubmoduleMode::Recurse,
+        )?))
+    }
+}
+
+fn save_cache(cache: &ParseCache) {
+    // The cache is only an optimisation, so failing to save it isn't fatal.
+    if let Err(error) = cache.save() {
+        warn!("Failed to save cache: {}", error);
+    }
+}
+
+fn run_pipeline(repo_root: PathBuf, config: Config, write_baseline: bool) -> anyhow::Result<()> {
+    let allow_filter = allow_filter(&repo_root, &config)?;
+    let cache = if config.cache() {
+        ParseCache::for_repo(&repo_root)
+    } else {
+        None
+    };
+    let owners_file_names = config.owners_file_names();
+    let load_options = LoadOptions {
+        allow_filter: &allow_filter,
+        owners_file_names: &owners_file_names,
+        submodule_mode: config.submodules(),
+        source: &WorkingTree {},
+        cache: cache.as_ref(),
+    };
+    pipeline::generate_codeowners_from_files(
+        Some(repo_root),
+        &config,
+        &WorkingTree {},
+        &load_options,
+        write_baseline,
+    )?;
+    if let Some(cache) = &cache {
+        save_cache(cache);
+    }
+    Ok(())
+}
+
+fn run_watch(repo_root: PathBuf, config: Config, debounce: Duration) -> anyhow::Result<()> {
+    let repo_root = repo_root.canonicalize()?;
+    // A cache is always used, so only changed files are parsed again, and so included files are
+    // known. It is only kept in memory with caching disabled.
+    let cache = if config.cache() {
+        ParseCache::for_repo(&repo_root)
+    } else {
+        None
+    }
+    .unwrap_or_else(ParseCache::in_memory);
+    let owners_file_names = config.owners_file_names();
+    // Changes are printed instead of writing to stdout.
+    let output_targets = config
+        .output_targets()
+        .into_iter()
+        .filter(|output_target| output_target.path.is_some())
+        .collect::<Vec<_>>();
+
+    let is_relevant = |path: &Path| {
+        let is_owners_file = path.file_name().map_or(false, |file_name| {
+            owners_file_names
+                .iter()
+                .any(|name| file_name == name.as_str())
+                || file_name == ".gitignore"
+                || file_name == IGNORE_FILE_NAME
+        });
+        // The index changes when files are staged, which changes the tracked OWNERS files.
+        is_owners_file
+            || path.ends_with(".git/index")
+            || path.ends_with(".git/info/exclude")
+            || cache.is_dependency(path)
+    };
+    let regenerate = || {
+        let allow_filter = allow_filter(&repo_root, &config)?;
+        let load_options = LoadOptions {
+            allow_filter: &allow_filter,
+            owners_file_names: &owners_file_names,
+            submodule_mode: config.submodules(),
+            source: &WorkingTree {},
+            cache: Some(&cache),
+        };
+        let (codeowners, comments) =
+            pipeline::load_checked_codeowners(&repo_root, &config, &load_options, false)?;
+        pipeline::check_codeowners_size(&codeowners, comments.as_ref(), &config)?;
+        pipeline::write_outputs(
+            &codeowners,
+            comments.as_ref(),
+            &output_targets,
+            &WorkingTree {},
+            config.message.as_ref(),
+        )?;
+        save_cache(&cache);
+        Ok(codeowners::to_codeowners_string(codeowners))
+    };
+    watch::watch(&repo_root, debounce, is_relevant, regenerate)
+}
+
+/// Generates from the files at `revision`, rather than the working tree.
+fn run_pipeline_at_revision(
+    repo_root: PathBuf,
+    config: Config,
+    revision: &str,
+    write_baseline: bool,
+) -> anyhow::Result<()> {
+    let repo_root = repo_root.canonicalize()?;
+    if config.allow_non_git_files() {
+        warn!(
+            "Ignoring allow-non-git-files, as only files at {} are used",
+            revision
+        );
+    }
+    let source = RevisionSource::new(&repo_root, &config, revision)?;
+    // NB: Outputs are still written to the working tree.
     pipeline::generate_codeowners_from_files(
-        args.repo_root,
-        args.output_file,
-        args.implicit_inherit.unwrap_or(DEFAULT_IMPLICIT_INHERIT),
-        allow_filter,
-        args.message,
+        Some(repo_root),
+        &config,
+        &WorkingTree {},
+        &source.load_options(None),
+        write_baseline,
     )
 }
 
+/// The files at a git revision, to load OWNERS files from without checking it out.
+struct RevisionSource {
+    tree: GitTree,
+    /// Only files in the revision's tree are read, so they're all allowed.
+    allow_list: AllowList,
+    owners_file_names: Vec<String>,
+    submodule_mode: SubmoduleMode,
+}
+
+impl RevisionSource {
+    /// The files at `revision` under the canonical `repo_root`.
+    fn new(repo_root: &Path, config: &Config, revision: &str) -> anyhow::Result<RevisionSource> {
+        let tree = GitTree::new(repo_root, revision)?;
+        let owners_file_names = config.owners_file_names();
+        let allow_list =
+            AllowList::from(tree.files().cloned().collect(), false, &owners_file_names)?;
+        Ok(RevisionSource {
+            tree,
+            allow_list,
+            owners_file_names,
+            submodule_mode: config.submodules(),
+        })
+    }
+
+    fn load_options<'a>(
+        &'a self,
+        cache: Option<&'a ParseCache>,
+    ) -> LoadOptions<'a, AllowList, String> {
+        LoadOptions {
+            allow_filter: &self.allow_list,
+            owners_file_names: &self.owners_file_names,
+            submodule_mode: self.submodule_mode,
+            source: &self.tree,
+            cache,
+        }
+    }
+}
+
+/// Loads the OWNERS files at `revision` under the canonical `repo_root`, returning the revision's
+/// tree along with the tree of OWNERS files.
+fn owners_tree_at_revision(
+    repo_root: &Path,
+    config: &Config,
+    revision: &str,
+) -> anyhow::Result<(GitTree, OwnersTree)> {
+    let source = RevisionSource::new(repo_root, config, revision)?;
+    let mut owners_tree = OwnersTree::load_from_files(repo_root, &source.load_options(None))?;
+    pipeline::apply_expiry(&mut owners_tree, config);
+    Ok((source.tree, owners_tree))
+}
+
+/// The effective owners of each file at `revision`, relative to the canonical `repo_root`.
+fn file_owners_at_revision(
+    repo_root: &Path,
+    config: &Config,
+    revision: &str,
+) -> anyhow::Result<FileOwners> {
+    let (tree, owners_tree) = owners_tree_at_revision(repo_root, config, revision)?;
+    let (codeowners, _) = pipeline::resolve_codeowners(&owners_tree, config)?;
+    let files = tree
+        .files()
+        .filter_map(|file| file.strip_prefix(repo_root).ok());
+    ownership_diff::file_owners(&codeowners, files)
+}
+
+fn run_diff(repo_root: PathBuf, config: Config, args: DiffArgs) -> anyhow::Result<()> {
+    let repo_root = repo_root.canonicalize()?;
+    let before = file_owners_at_revision(&repo_root, &config, &args.rev_a)?;
+    let after = file_owners_at_revision(&repo_root, &config, &args.rev_b)?;
+
+    let changes = ownership_diff::diff_file_owners(&before, &after, args.only_lost_owners);
+    // Files gaining owners by being added aren't losing any.
+    let added = if args.only_lost_owners {
+        BTreeMap::new()
+    } else {
+        ownership_diff::added_files(&before, &after)
+    };
+    let removed = ownership_diff::removed_files(&before, &after);
+    let report = if args.per_file {
+        ownership_diff::to_diff_report(
+            &ownership_diff::per_file_changes(&changes),
+            &ownership_diff::per_file_changes(&added),
+            &ownership_diff::per_file_changes(&removed),
+        )
+    } else {
+        let compared = before.keys().filter(|file| after.contains_key(*file));
+        ownership_diff::to_diff_report(
+            &ownership_diff::collapse_changes(&changes, compared),
+            &ownership_diff::collapse_changes(&added, after.keys()),
+            &ownership_diff::collapse_changes(&removed, before.keys()),
+        )
+    };
+    println!("{}", report);
+    if args.exit_code && !(changes.is_empty() && added.is_empty() && removed.is_empty()) {
+        return Err(anyhow!(
+            "Ownership changed between {} and {} for {} existing, {} added and {} removed files",
+            args.rev_a,
+            args.rev_b,
+            changes.len(),
+            added.len(),
+            removed.len()
+        ));
+    }
+    Ok(())
+}
+
+fn run_stats(
+    repo_root: PathBuf,
+    config: Config,
+    revision: Option<&str>,
+    args: StatsArgs,
+) -> anyhow::Result<()> {
+    let repo_root = repo_root.canonicalize()?;
+    // Always parsed through a cache, which records the files each OWNERS file includes.
+    let cache = ParseCache::in_memory();
+    let owners_file_names = config.owners_file_names();
+    let (allow_filter, source, files): (Box<dyn AllowFilter>, Box<dyn FileSource>, _) =
+        match revision {
+            Some(revision) => {
+                let source = RevisionSource::new(&repo_root, &config, revision)?;
+                let files = source.tree.files().cloned().collect::<HashSet<_>>();
+                (Box::new(source.allow_list), Box::new(source.tree), files)
+            }
+            None => {
+                let recurse_submodules = config.submodules() == SubmoduleMode::Recurse;
+                (
+                    allow_filter(&repo_root, &config)?,
+                    Box::new(WorkingTree {}),
+                    git::tracked_files(&repo_root, recurse_submodules)?,
+                )
+            }
+        };
+    let load_options = LoadOptions {
+        allow_filter: &allow_filter,
+        owners_file_names: &owners_file_names,
+        submodule_mode: config.submodules(),
+        source: source.as_ref(),
+        cache: Some(&cache),
+    };
+    let mut tree = OwnersTree::load_from_files(&repo_root, &load_options)?;
+    pipeline::apply_expiry(&mut tree, &config);
+    let files = files
+        .iter()
+        .filter_map(|file| file.strip_prefix(&repo_root).ok());
+    let stats = stats::compute_stats(
+        &tree,
+        config.implicit_inherit(),
+        &cache.included_files(),
+        files,
+    )?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        println!("{}", stats::to_stats_string(&stats, args.top));
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     env_logger::Builder::new()
         .filter_level(args.verbose.log_level_filter())
         .init();
 
-    if args.allow_non_git_files {
-        let allow_filter = FilterGitMetadata {};
-        run_pipeline(args, &allow_filter)
-    } else {
-        let allow_filter = AllowList::allow_git_files()?;
-        run_pipeline(args, &allow_filter)
+    let repo_root = match &args.repo_root {
+        Some(repo_root) => repo_root.clone(),
+        None => std::env::current_dir()?,
+    };
+    let file_config = match &args.config {
+        Some(config_file) => Config::from_file(config_file)?,
+        None => Config::discover(&repo_root)?,
+    };
+    let config = file_config.merge(args.to_config());
+
+    if args.write_baseline {
+        if config.baseline.is_none() {
+            return Err(anyhow!(
+                "--write-baseline needs a baseline path to write to."
+            ));
+        }
+        if args.command.is_some() {
+            return Err(anyhow!("--write-baseline is only used when generating."));
+        }
+    }
+
+    match args.command {
+        Some(Command::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            print!("{}", config.with_defaults().to_toml()?);
+            Ok(())
+        }
+        Some(Command::Diff(args)) => run_diff(repo_root, config, args),
+        Some(Command::Stats(stats_args)) => {
+            run_stats(repo_root, config, args.rev.as_deref(), stats_args)
+        }
+        Some(Command::Watch { .. }) if args.rev.is_some() => Err(anyhow!(
+            "--rev can't be used with watch, which uses the working tree."
+        )),
+        Some(Command::Watch { debounce_ms }) => {
+            run_watch(repo_root, config, Duration::from_millis(debounce_ms))
+        }
+        None => match &args.rev {
+            Some(revision) => {
+                run_pipeline_at_revision(repo_root, config, revision, args.write_baseline)
+            }
+            None => run_pipeline(repo_root, config, args.write_baseline),
+        },
     }
 }
@@ -1,59 +1,145 @@
+use crate::cache::content_hash;
+use crate::file_source::FileSource;
 use crate::owners_set::OwnersSet;
 use anyhow::anyhow;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::HashMap;
-use std::fs;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::path::PathBuf;
 
-#[derive(PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
 pub struct OwnersFileConfig {
     pub all_files: OwnersSet,
     pub pattern_overrides: HashMap<String, OwnersSet>,
 }
 
+/// Everything read while parsing an OWNERS file, other than the file itself, used to tell whether
+/// a previous parse is still valid.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct ParseDependencies {
+    /// Content hashes of included files.
+    pub files: BTreeMap<PathBuf, String>,
+    /// Include glob patterns, which may match different files over time.
+    pub globs: Vec<GlobDependency>,
+    /// Each included file, mapped to the file which included it.
+    pub included_by: BTreeMap<PathBuf, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct GlobDependency {
+    /// The file containing the include.
+    pub current_path: PathBuf,
+    pub include_path: String,
+    pub matches: Vec<PathBuf>,
+}
+
+impl ParseDependencies {
+    /// Whether the included files and glob matches in `source` are unchanged since parsing.
+    pub fn is_current<P: AsRef<Path>>(&self, source: &dyn FileSource, repo_base: P) -> bool {
+        let files_unchanged = self.files.iter().all(|(path, hash)| {
+            source
+                .read_to_string(path)
+                .map_or(false, |text| content_hash(&text) == *hash)
+        });
+        files_unchanged
+            && self.globs.iter().all(|glob| {
+                resolve_include_path(
+                    source,
+                    repo_base.as_ref(),
+                    &glob.current_path,
+                    &glob.include_path,
+                )
+                .map_or(false, |matches| matches == glob.matches)
+            })
+    }
+
+    /// Whether a change to `path` could affect the parse, i.e., it is an included file or could
+    /// match an include glob.
+    pub fn depends_on<P0: AsRef<Path>, P1: AsRef<Path>>(&self, repo_base: P0, path: P1) -> bool {
+        let path = path.as_ref();
+        self.files.contains_key(path)
+            || self.globs.iter().any(|glob| {
+                include_glob_pattern(repo_base.as_ref(), &glob.current_path, &glob.include_path)
+                    .and_then(|pattern| Ok(glob::Pattern::new(&pattern)?))
+                    .map_or(false, |pattern| pattern.matches_path(path))
+            })
+    }
+}
+
 impl OwnersFileConfig {
+    /// Parses the OWNERS file at `path`, reading it and any included files from `source`.
     pub fn from_file<P0: AsRef<Path>, P1: AsRef<Path>>(
+        source: &dyn FileSource,
         path: P0,
         repo_base: P1,
     ) -> anyhow::Result<OwnersFileConfig> {
-        let path_ref = path.as_ref();
-        let text = fs::read_to_string(path_ref)?;
-        Self::from_text(text, path.as_ref(), repo_base.as_ref())
+        Ok(Self::from_file_with_dependencies(source, path, repo_base)?.0)
     }
 
-    fn from_text<S: AsRef<str>, P0: AsRef<Path>, P1: AsRef<Path>>(
+    /// Parses the OWNERS file at `path`, also returning what the result depends on.
+    pub fn from_file_with_dependencies<P0: AsRef<Path>, P1: AsRef<Path>>(
+        source: &dyn FileSource,
+        path: P0,
+        repo_base: P1,
+    ) -> anyhow::Result<(OwnersFileConfig, ParseDependencies)> {
+        let text = source.read_to_string(path.as_ref())?;
+        Self::from_text_with_dependencies(source, text, path, repo_base)
+    }
+
+    /// Parses the text of the OWNERS file at `path`, also returning what the result depends on.
+    pub fn from_text_with_dependencies<S: AsRef<str>, P0: AsRef<Path>, P1: AsRef<Path>>(
+        source: &dyn FileSource,
         text: S,
         path: P0,
         repo_base: P1,
-    ) -> anyhow::Result<OwnersFileConfig> {
-        let mut config = OwnersFileConfig::default();
-        Self::parse_text(
-            &mut config,
+    ) -> anyhow::Result<(OwnersFileConfig, ParseDependencies)> {
+        let mut dependencies = ParseDependencies::default();
+        let config = Self::parse_text(
+            source,
             text.as_ref(),
             path.as_ref(),
             repo_base.as_ref(),
             &mut HashMap::new(),
+            &mut dependencies,
         )?;
-        Ok(config)
+        Ok((config, dependencies))
+    }
+
+    #[cfg(test)]
+    fn from_text<S: AsRef<str>, P0: AsRef<Path>, P1: AsRef<Path>>(
+        source: &dyn FileSource,
+        text: S,
+        path: P0,
+        repo_base: P1,
+    ) -> anyhow::Result<OwnersFileConfig> {
+        Ok(Self::from_text_with_dependencies(source, text, path, repo_base)?.0)
     }
 
     fn parse_text<P0: AsRef<Path>, P1: AsRef<Path>>(
-        config: &mut OwnersFileConfig,
+        file_source: &dyn FileSource,
         text: &str,
         path: P0,
         repo_base: P1,
         seen_owners_files: &mut HashMap<PathBuf, Option<PathBuf>>,
-    ) -> anyhow::Result<()> {
+        dependencies: &mut ParseDependencies,
+    ) -> anyhow::Result<OwnersFileConfig> {
+        let mut config = OwnersFileConfig::default();
+        // Included files are parsed into their own configs and merged in once this file has been
+        // fully parsed, so that explicit `set` statements here take precedence over included ones.
+        // Each entry is keyed by the pattern section the include appeared in, if any.
+        let mut included_configs: Vec<(Option<String>, PathBuf, OwnersFileConfig)> = Vec::new();
         // `active_pattern_key` tracks the current context.
         // `None`: Modifying `config.all_files`.
         // `Some(key)`: Modifying `config.pattern_overrides` for the given key.
         let mut active_pattern_key: Option<String> = None;
-        let source = path
-            .as_ref()
-            .to_str()
-            .expect("Error converting file path to string");
+        let source = path.as_ref().to_str().ok_or_else(|| {
+            anyhow!(
+                "Error converting file path '{}' to string",
+                path.as_ref().display()
+            )
+        })?;
 
         if seen_owners_files.is_empty() {
             seen_owners_files.insert(path.as_ref().to_path_buf(), None);
@@ -66,42 +152,85 @@ impl OwnersFileConfig {
             }
             let line_number = i + 1;
 
-            if let Some(include_file) = maybe_get_include(line)
+            if let Some(include) = maybe_get_include(line)
                 .map_err(|error| anyhow!("{} Found at {}:{}", error, source, line_number))?
             {
-                if active_pattern_key.is_some() {
+                if let (Some(key), IncludeSelector::Pattern(pattern)) =
+                    (&active_pattern_key, &include.selector)
+                {
                     return Err(anyhow!(
-                        "include is not allowed in path-specific sections. Found at {}:{}",
+                        "Include selector [{}] can't be used within the [{}] file pattern section, as file patterns can't be combined. Use ':owners-only' instead. Found at {}:{}",
+                        pattern,
+                        key,
                         source,
                         line_number
                     ));
                 }
+                let include_paths = resolve_include_path(
+                    file_source,
+                    repo_base.as_ref(),
+                    path.as_ref(),
+                    &include.path,
+                )
+                .map_err(|error| anyhow!("{} Found at {}:{}", error, source, line_number))?;
+                if is_glob(&include.path) {
+                    dependencies.globs.push(GlobDependency {
+                        current_path: path.as_ref().to_path_buf(),
+                        include_path: include.path.clone(),
+                        matches: include_paths.clone(),
+                    });
+                }
 
-                let include_path =
-                    resolve_include_path(repo_base.as_ref(), path.as_ref(), &include_file)
-                        .map_err(|error| {
+                for include_path in include_paths {
+                    let include_text =
+                        file_source.read_to_string(&include_path).map_err(|error| {
+                            anyhow!(
+                                "{} Found at {}:{}",
+                                error,
+                                include_path.display(),
+                                line_number
+                            )
+                        })?;
+                    dependencies
+                        .files
+                        .insert(include_path.clone(), content_hash(&include_text));
+
+                    check_no_circular_include(&include_path, seen_owners_files)?;
+                    seen_owners_files
+                        .insert(include_path.clone(), Some(path.as_ref().to_path_buf()));
+                    dependencies
+                        .included_by
+                        .insert(include_path.clone(), path.as_ref().to_path_buf());
+
+                    let included_config = Self::parse_text(
+                        file_source,
+                        &include_text,
+                        &include_path,
+                        repo_base.as_ref(),
+                        seen_owners_files,
+                        dependencies,
+                    )?;
+                    let included_config =
+                        include.selector.apply(included_config).map_err(|error| {
                             anyhow!("{} Found at {}:{}", error, source, line_number)
                         })?;
-
-                let include_text = fs::read_to_string(&include_path).map_err(|error| {
-                    anyhow!(
-                        "{} Found at {}:{}",
-                        error,
-                        include_path.display(),
-                        line_number
-                    )
-                })?;
-
-                check_no_circular_include(&include_path, seen_owners_files)?;
-                seen_owners_files.insert(include_path.clone(), Some(path.as_ref().to_path_buf()));
-
-                Self::parse_text(
-                    config,
-                    &include_text,
-                    &include_path,
-                    repo_base.as_ref(),
-                    seen_owners_files,
-                )?;
+                    // Within a file pattern section, the included directory level owners apply to
+                    // that pattern. The included file's own patterns can't be combined with it.
+                    if active_pattern_key.is_some() && !included_config.pattern_overrides.is_empty()
+                    {
+                        return Err(anyhow!(
+                            "Included file '{}' has file pattern sections, which are not allowed when included from a file pattern section. Found at {}:{}",
+                            include_path.display(),
+                            source,
+                            line_number
+                        ));
+                    }
+                    included_configs.push((
+                        active_pattern_key.clone(),
+                        include_path,
+                        included_config,
+                    ));
+                }
                 continue;
             }
 
@@ -119,15 +248,6 @@ impl OwnersFileConfig {
                 .maybe_process_set(line)
                 .map_err(|error| anyhow!("{} Encountered at {}:{}", error, source, line_number))?;
             if is_set_line {
-                // If there's more than one seen_owners_files, then we're inside an include where
-                // set statements aren't allowed.
-                if seen_owners_files.len() > 1 {
-                    return Err(anyhow!(
-                        "set statements are not allowed inside includes. Found at {}:{}",
-                        source,
-                        line_number
-                    ));
-                }
                 continue;
             }
 
@@ -136,19 +256,89 @@ impl OwnersFileConfig {
                 continue;
             }
 
-            if line.contains(char::is_whitespace) {
+            current_set
+                .process_owner(line)
+                .map_err(|error| anyhow!("{} Found at {}:{}", error, source, line_number))?;
+        }
+        seen_owners_files.remove(path.as_ref());
+
+        let mut inherit_sources = HashMap::new();
+        for (pattern_key, include_path, included_config) in included_configs {
+            let target = match pattern_key {
+                Some(ref key) => config.pattern_overrides.entry(key.clone()).or_default(),
+                None => &mut config.all_files,
+            };
+            merge_owners_set(
+                target,
+                included_config.all_files,
+                pattern_key,
+                source,
+                &include_path,
+                &mut inherit_sources,
+            )?;
+            for (pattern, owners_set) in included_config.pattern_overrides {
+                merge_owners_set(
+                    config.pattern_overrides.entry(pattern.clone()).or_default(),
+                    owners_set,
+                    Some(pattern),
+                    source,
+                    &include_path,
+                    &mut inherit_sources,
+                )?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Merges the owners of an `include`d section into the including file's section.
+///
+/// An explicit `set inherit` in the including file always wins. Otherwise, the first included
+/// file to set inherit for a section provides the value, and a later include setting a different
+/// value for the same section is an error. `inherit_sources` tracks which include provided the
+/// value for each section, keyed by pattern (`None` for directory level owners).
+fn merge_owners_set(
+    target: &mut OwnersSet,
+    included: OwnersSet,
+    section: Option<String>,
+    source: &str,
+    include_path: &Path,
+    inherit_sources: &mut HashMap<Option<String>, (bool, PathBuf)>,
+) -> anyhow::Result<()> {
+    for owner in included.owners {
+        let expires = included.expiries.get(&owner).copied();
+        target.add(owner, expires);
+    }
+
+    let Some(included_inherit) = included.inherit else {
+        return Ok(());
+    };
+    match inherit_sources.get(&section) {
+        Some((inherit, first_include_path)) => {
+            if *inherit != included_inherit {
+                let section_name = section
+                    .map(|pattern| format!("[{}]", pattern))
+                    .unwrap_or_else(|| "directory level owners".to_string());
                 return Err(anyhow!(
-                    "Invalid user/group '{}' cannot contain whitespace. Found at {}:{}",
-                    line,
+                    "Conflicting inherit values for {} included from '{}' (inherit = {}) and '{}' (inherit = {}). \
+                    Set inherit explicitly in {} to resolve.",
+                    section_name,
+                    first_include_path.display(),
+                    inherit,
+                    include_path.display(),
+                    included_inherit,
                     source,
-                    line_number
                 ));
             }
-            current_set.owners.insert(line.to_string());
         }
-        seen_owners_files.remove(path.as_ref());
-        Ok(())
+        None => {
+            if target.inherit.is_none() {
+                target.inherit = Some(included_inherit);
+                inherit_sources.insert(section, (included_inherit, include_path.to_path_buf()));
+            }
+        }
     }
+    Ok(())
 }
 
 /// Remove extraneous info in the line, such as comments and surrounding whitespace.
@@ -169,24 +359,84 @@ fn maybe_get_file_pattern(line: &str) -> Option<String> {
     }
 }
 
-/// Parses an include directive, e.g., `include path/to/another/OWNERS`.
-fn maybe_get_include(line: &str) -> anyhow::Result<Option<String>> {
+/// An include directive, e.g., `include path/to/another/OWNERS [*.sql]`.
+#[derive(PartialEq, Debug)]
+struct Include {
+    path: String,
+    selector: IncludeSelector,
+}
+
+/// Selects which parts of an included OWNERS file are used.
+#[derive(PartialEq, Debug)]
+enum IncludeSelector {
+    /// Both directory level owners and file pattern sections. This is the default.
+    All,
+    /// Only directory level owners, e.g., `include path/to/OWNERS :owners-only`.
+    OwnersOnly,
+    /// Only the given file pattern section, e.g., `include path/to/OWNERS [*.sql]`.
+    Pattern(String),
+}
+
+impl IncludeSelector {
+    fn parse(selector: &str) -> anyhow::Result<IncludeSelector> {
+        if let Some(pattern) = maybe_get_file_pattern(selector) {
+            return Ok(IncludeSelector::Pattern(pattern));
+        }
+        match selector {
+            ":all" => Ok(IncludeSelector::All),
+            ":owners-only" => Ok(IncludeSelector::OwnersOnly),
+            _ => Err(anyhow!(
+                "Invalid include selector '{}'. Expected '[<pattern>]', ':owners-only' or ':all'.",
+                selector
+            )),
+        }
+    }
+
+    /// Reduces the included config down to the selected sections.
+    fn apply(&self, mut config: OwnersFileConfig) -> anyhow::Result<OwnersFileConfig> {
+        match self {
+            IncludeSelector::All => Ok(config),
+            IncludeSelector::OwnersOnly => Ok(OwnersFileConfig {
+                all_files: config.all_files,
+                ..OwnersFileConfig::default()
+            }),
+            IncludeSelector::Pattern(pattern) => {
+                let owners_set = config.pattern_overrides.remove(pattern).ok_or_else(|| {
+                    anyhow!("Selected section [{}] not found in included file.", pattern)
+                })?;
+                Ok(OwnersFileConfig {
+                    pattern_overrides: HashMap::from([(pattern.clone(), owners_set)]),
+                    ..OwnersFileConfig::default()
+                })
+            }
+        }
+    }
+}
+
+/// Parses an include directive, e.g., `include path/to/another/OWNERS`, with an optional selector.
+fn maybe_get_include(line: &str) -> anyhow::Result<Option<Include>> {
     lazy_static! {
-        // Ensures the path is non-empty and doesn't contain whitespace.
-        static ref RE: Regex = Regex::new(r"^\s*include\s+(?<path>\S+)\s*$").unwrap();
+        // Ensures the path is non-empty and doesn't contain whitespace. A `[pattern]` selector may
+        // have whitespace within its brackets, as file pattern lines may.
+        static ref RE: Regex =
+            Regex::new(r"^\s*include\s+(?<path>\S+)(\s+(?<selector>\[[^\]]*\]|\S+))?\s*$")
+                .unwrap();
         static ref MALFORMED_RE: Regex = Regex::new(r"^\s*include\s*$").unwrap();
     }
     if let Some(captures) = RE.captures(line) {
         let path = captures["path"].to_string();
-        dbg!(captures["path"].to_string());
         if path.is_empty() {
             return Err(anyhow!("Invalid include. Expected non-empty include path."));
         }
+        let selector = match captures.name("selector") {
+            Some(selector) => IncludeSelector::parse(selector.as_str())?,
+            None => IncludeSelector::All,
+        };
 
-        Ok(Some(path))
+        Ok(Some(Include { path, selector }))
     } else if MALFORMED_RE.is_match(line) || line.to_lowercase().starts_with("include ") {
         Err(anyhow!(
-            "Invalid include format '{}'. Expected 'include <path>'.",
+            "Invalid include format '{}'. Expected 'include <path> [selector]'.",
             line,
         ))
     } else {
@@ -194,45 +444,116 @@ fn maybe_get_include(line: &str) -> anyhow::Result<Option<String>> {
     }
 }
 
+/// Resolves an include path to the files it refers to.
+///
+/// Include paths may contain glob patterns, e.g., `/shared/policies/*/OWNERS`, in which case all
+/// matching files are returned in sorted order. A pattern which matches no files is an error.
 fn resolve_include_path<P0: AsRef<Path>, P1: AsRef<Path>, P2: AsRef<Path>>(
+    source: &dyn FileSource,
     repo_base: P0,
     current_path: P1,
     include_path: P2,
-) -> anyhow::Result<PathBuf> {
+) -> anyhow::Result<Vec<PathBuf>> {
     let repo_base_path = repo_base.as_ref();
-    let current_path_ref = current_path.as_ref();
     let include_path_ref = include_path.as_ref();
+    let (base_dir, relative_path) =
+        split_include_path(repo_base_path, current_path.as_ref(), include_path_ref)?;
+
+    if !is_glob(relative_path) {
+        let path = base_dir.join(relative_path);
+        return Ok(vec![canonicalize_include_path(
+            source,
+            &path,
+            repo_base_path,
+        )?]);
+    }
 
-    let current_dir = current_path_ref.parent().ok_or_else(|| {
-        anyhow::anyhow!(
-            "current_path has no parent directory: {:?}",
-            current_path_ref
-        )
+    let pattern = include_glob_pattern(repo_base_path, current_path.as_ref(), include_path_ref)?;
+    let mut paths = Vec::new();
+    for path in source
+        .glob(&pattern)
+        .map_err(|error| anyhow!("Invalid include pattern '{}': {}", pattern, error))?
+    {
+        paths.push(canonicalize_include_path(source, &path, repo_base_path)?);
+    }
+    if paths.is_empty() {
+        return Err(anyhow!(
+            "Include pattern '{}' did not match any files.",
+            include_path_ref.display()
+        ));
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Splits an include path into the directory it is relative to and the path relative to that.
+fn split_include_path<'a>(
+    repo_base: &'a Path,
+    current_path: &'a Path,
+    include_path: &'a Path,
+) -> anyhow::Result<(&'a Path, &'a Path)> {
+    let current_dir = current_path.parent().ok_or_else(|| {
+        anyhow::anyhow!("current_path has no parent directory: {:?}", current_path)
     })?;
 
-    let path = if include_path_ref.is_absolute() {
-        repo_base_path.join(
-            include_path_ref
+    if include_path.is_absolute() {
+        Ok((
+            repo_base,
+            include_path
                 .strip_prefix("/")
-                .or_else(|_| include_path_ref.strip_prefix("\\"))
-                .unwrap_or(include_path_ref),
-        )
+                .or_else(|_| include_path.strip_prefix("\\"))
+                .unwrap_or(include_path),
+        ))
     } else {
-        current_dir.join(include_path_ref)
-    };
+        Ok((current_dir, include_path))
+    }
+}
+
+/// The glob pattern for an include path, with absolute paths.
+fn include_glob_pattern<P0: AsRef<Path>, P1: AsRef<Path>, P2: AsRef<Path>>(
+    repo_base: P0,
+    current_path: P1,
+    include_path: P2,
+) -> anyhow::Result<String> {
+    let (base_dir, relative_path) = split_include_path(
+        repo_base.as_ref(),
+        current_path.as_ref(),
+        include_path.as_ref(),
+    )?;
+    // Only the include path itself is treated as a pattern, not the directory it's relative to.
+    Ok(
+        Path::new(&glob::Pattern::escape(&base_dir.to_string_lossy()))
+            .join(relative_path)
+            .to_string_lossy()
+            .to_string(),
+    )
+}
+
+fn is_glob<P: AsRef<Path>>(include_path: P) -> bool {
+    include_path
+        .as_ref()
+        .to_string_lossy()
+        .contains(['*', '?', '['])
+}
 
-    let canonicalized_path = fs::canonicalize(&path).map_err(|error| {
+fn canonicalize_include_path(
+    source: &dyn FileSource,
+    path: &Path,
+    repo_base: &Path,
+) -> anyhow::Result<PathBuf> {
+    let canonicalized_path = source.canonicalize(path).map_err(|error| {
         anyhow!(
             "Failed to canonicalize include path '{}': {}",
             path.display(),
             error
         )
     })?;
-    if !canonicalized_path.starts_with(repo_base_path) {
+    if !canonicalized_path.starts_with(repo_base) {
         return Err(anyhow!(
             "Include path '{}' is outside the repository base '{}'.",
             canonicalized_path.display(),
-            repo_base_path.display()
+            repo_base.display()
         ));
     }
     Ok(canonicalized_path)
@@ -269,10 +590,13 @@ fn check_no_circular_include(
 
 #[cfg(test)]
 mod tests {
-    use crate::owners_file::{maybe_get_file_pattern, maybe_get_include, OwnersFileConfig};
+    use crate::file_source::WorkingTree;
+    use crate::owners_file::{
+        maybe_get_file_pattern, maybe_get_include, Include, IncludeSelector, OwnersFileConfig,
+    };
     use crate::owners_set::OwnersSet;
     use indoc::indoc;
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet};
 
     #[test]
     fn parse_blanket_owners_only() -> anyhow::Result<()> {
@@ -289,11 +613,12 @@ mod tests {
                     .into_iter()
                     .map(|s| s.to_string())
                     .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
             },
             pattern_overrides: HashMap::default(),
         };
 
-        let parsed = OwnersFileConfig::from_text(input, "test data", "test data")?;
+        let parsed = OwnersFileConfig::from_text(&WorkingTree {}, input, "test data", "test data")?;
         assert_eq!(parsed, expected);
         Ok(())
     }
@@ -314,11 +639,12 @@ mod tests {
                     .into_iter()
                     .map(|s| s.to_string())
                     .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
             },
             pattern_overrides: HashMap::default(),
         };
 
-        let parsed = OwnersFileConfig::from_text(input, "test data", "test data")?;
+        let parsed = OwnersFileConfig::from_text(&WorkingTree {}, input, "test data", "test data")?;
         assert_eq!(parsed, expected);
         Ok(())
     }
@@ -341,6 +667,7 @@ mod tests {
                     .into_iter()
                     .map(|s| s.to_string())
                     .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
             },
             pattern_overrides: HashMap::from([(
                 "*.rs".to_string(),
@@ -350,11 +677,12 @@ mod tests {
                         .into_iter()
                         .map(|s| s.to_string())
                         .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
                 },
             )]),
         };
 
-        let parsed = OwnersFileConfig::from_text(input, "test data", "test data")?;
+        let parsed = OwnersFileConfig::from_text(&WorkingTree {}, input, "test data", "test data")?;
         assert_eq!(parsed, expected);
         Ok(())
     }
@@ -376,11 +704,17 @@ mod tests {
     fn test_maybe_get_include() -> anyhow::Result<()> {
         assert_eq!(
             maybe_get_include("include foo/bar.owners")?,
-            Some("foo/bar.owners".to_string())
+            Some(Include {
+                path: "foo/bar.owners".to_string(),
+                selector: IncludeSelector::All,
+            })
         );
         assert_eq!(
             maybe_get_include("  include   my_path   ")?,
-            Some("my_path".to_string())
+            Some(Include {
+                path: "my_path".to_string(),
+                selector: IncludeSelector::All,
+            })
         );
         assert!(maybe_get_include("include").is_err());
         assert!(maybe_get_include("include ").is_err());
@@ -388,4 +722,39 @@ mod tests {
         assert_eq!(maybe_get_include("not an include")?, None);
         Ok(())
     }
+
+    #[test]
+    fn test_maybe_get_include_with_selector() -> anyhow::Result<()> {
+        assert_eq!(
+            maybe_get_include("include /db/OWNERS [*.sql]")?,
+            Some(Include {
+                path: "/db/OWNERS".to_string(),
+                selector: IncludeSelector::Pattern("*.sql".to_string()),
+            })
+        );
+        assert_eq!(
+            maybe_get_include("include /db/OWNERS :owners-only")?,
+            Some(Include {
+                path: "/db/OWNERS".to_string(),
+                selector: IncludeSelector::OwnersOnly,
+            })
+        );
+        assert_eq!(
+            maybe_get_include("include /db/OWNERS :all")?,
+            Some(Include {
+                path: "/db/OWNERS".to_string(),
+                selector: IncludeSelector::All,
+            })
+        );
+        assert_eq!(
+            maybe_get_include("include /db/OWNERS [ *.sql ]")?,
+            Some(Include {
+                path: "/db/OWNERS".to_string(),
+                selector: IncludeSelector::Pattern("*.sql".to_string()),
+            })
+        );
+        assert!(maybe_get_include("include /db/OWNERS :unknown").is_err());
+        assert!(maybe_get_include("include /db/OWNERS *.sql").is_err());
+        Ok(())
+    }
 }
@@ -1,15 +1,65 @@
+use crate::expiry::Date;
 use anyhow::anyhow;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 
-#[derive(PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
 pub struct OwnersSet {
     pub inherit: Option<bool>,
     pub owners: HashSet<String>,
+    /// When temporary owners expire, by owner. Owners listed without an expiry anywhere are
+    /// permanent.
+    pub expiries: BTreeMap<String, Date>,
 }
 
 impl OwnersSet {
+    /// Adds an owner, which is temporary if it `expires`. Once permanent, an owner stays so, and
+    /// the latest expiry wins.
+    pub fn add(&mut self, owner: String, expires: Option<Date>) {
+        match expires {
+            Some(expires) => {
+                if !self.owners.contains(&owner) || self.expiries.contains_key(&owner) {
+                    let expiry = self.expiries.entry(owner.clone()).or_insert(expires);
+                    *expiry = (*expiry).max(expires);
+                    self.owners.insert(owner);
+                }
+            }
+            None => {
+                self.expiries.remove(&owner);
+                self.owners.insert(owner);
+            }
+        }
+    }
+
+    /// Adds the owner on an owner line, e.g., `ada.lovelace`, or `ada.lovelace expires=2026-12-31`
+    /// for a temporary owner.
+    pub fn process_owner(&mut self, line: &str) -> anyhow::Result<()> {
+        let mut parts = line.split_whitespace();
+        let owner = parts.next().unwrap_or_default();
+        let mut expires = None;
+        for annotation in parts {
+            match annotation.split_once('=') {
+                Some(("expires", date)) => expires = Some(date.parse()?),
+                Some((name, _)) => {
+                    return Err(anyhow!(
+                        "Invalid owner annotation '{}'. Expected 'expires=YYYY-MM-DD'.",
+                        name
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "Invalid user/group '{}' cannot contain whitespace.",
+                        line
+                    ))
+                }
+            }
+        }
+        self.add(owner.to_string(), expires);
+        Ok(())
+    }
+
     /// Evaluates the line for set variable syntax. If found, the variable specified will be updated
     /// to match the value specified.
     ///
@@ -57,6 +107,7 @@ impl OwnersSet {
 #[cfg(test)]
 mod tests {
     use crate::owners_set::OwnersSet;
+    use std::collections::{BTreeMap, HashSet};
 
     #[test]
     fn process_set_non_set() -> anyhow::Result<()> {
@@ -95,6 +146,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn process_owner_expires() -> anyhow::Result<()> {
+        let mut owners_set = OwnersSet::default();
+        owners_set.process_owner("ada.lovelace")?;
+        owners_set.process_owner("grace.hopper expires=2026-12-31")?;
+        owners_set.process_owner("grace.hopper expires=2026-06-30")?;
+        // Permanent, as also listed without an expiry
+        owners_set.process_owner("ada.lovelace expires=2026-01-01")?;
+        assert_eq!(
+            owners_set.owners,
+            HashSet::from(["ada.lovelace".to_string(), "grace.hopper".to_string()])
+        );
+        assert_eq!(
+            owners_set.expiries,
+            BTreeMap::from([("grace.hopper".to_string(), "2026-12-31".parse()?)])
+        );
+
+        assert!(is_error_with_text(
+            owners_set.process_owner("mary.jackson expires=2026-13-01"),
+            "Invalid date"
+        ));
+        assert!(is_error_with_text(
+            owners_set.process_owner("mary.jackson until=2026-12-31"),
+            "Invalid owner annotation"
+        ));
+        assert!(is_error_with_text(
+            owners_set.process_owner("mary jackson"),
+            "cannot contain whitespace"
+        ));
+        Ok(())
+    }
+
     fn is_error_with_text<T>(result: anyhow::Result<T>, contents: &str) -> bool {
         if result.is_ok() {
             return false;
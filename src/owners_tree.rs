@@ -1,125 +1,432 @@
 use crate::allow_filter::AllowFilter;
+use crate::cache::ParseCache;
+use crate::file_source::FileSource;
 use crate::owners_file::OwnersFileConfig;
-use log::{debug, trace};
-use std::fs;
+use anyhow::anyhow;
+use log::{debug, trace, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(PartialEq, Debug, Default)]
 pub struct TreeNode {
     pub path: PathBuf,
     pub repo_base: PathBuf,
+    /// The OWNERS file loaded into `owners_config`, if there is one.
+    pub owners_file: Option<PathBuf>,
+    /// Files included by `owners_file`, directly or through other included files, each mapped to
+    /// the file which included it.
+    pub includes: BTreeMap<PathBuf, PathBuf>,
     pub owners_config: OwnersFileConfig,
     pub children: Vec<TreeNode>,
+    /// Submodules directly within this node's part of the tree, owned as single opaque paths.
+    pub opaque_submodules: Vec<PathBuf>,
 }
 
 pub type OwnersTree = TreeNode;
 
 impl TreeNode {
-    pub fn new<P0: AsRef<Path>, P1: AsRef<Path>>(path: P0, repo_base: P1) -> TreeNode {
-        TreeNode {
-            path: path
-                .as_ref()
-                .to_path_buf()
-                .canonicalize()
-                .expect("Failed to canonicalize path"),
-            repo_base: repo_base
-                .as_ref()
-                .to_path_buf()
-                .canonicalize()
-                .expect("Failed to canonicalize path"),
+    /// A node for the directory at `path` in `source`, without loading its OWNERS file.
+    pub fn new<P0: AsRef<Path>, P1: AsRef<Path>>(
+        path: P0,
+        repo_base: P1,
+        source: &dyn FileSource,
+    ) -> anyhow::Result<TreeNode> {
+        Ok(TreeNode {
+            path: source.canonicalize(path.as_ref())?,
+            repo_base: source.canonicalize(repo_base.as_ref())?,
             ..TreeNode::default()
-        }
+        })
     }
 
-    pub fn maybe_load_owners_file<F>(&mut self, allow_filter: &F) -> anyhow::Result<bool>
+    /// Loads the OWNERS file in this node's directory, if there is an allowed one.
+    ///
+    /// If more than one accepted OWNERS file name is present in the directory, the first is used.
+    /// The directory itself is taken to be allowed, as nodes are only made for allowed directories.
+    pub fn maybe_load_owners_file<F, S>(
+        &mut self,
+        options: &LoadOptions<F, S>,
+    ) -> anyhow::Result<bool>
     where
         F: AllowFilter,
+        S: AsRef<str>,
     {
-        let owners_file = self.path.join("OWNERS");
-        if !owners_file.exists() || !owners_file.is_file() {
+        let mut owners_files = options
+            .owners_file_names
+            .iter()
+            .map(|name| self.path.join(name.as_ref()))
+            .filter(|owners_file| options.source.is_file(owners_file))
+            .filter(|owners_file| {
+                if options.allow_filter.allowed_in_walk(owners_file, false) {
+                    true
+                } else {
+                    trace!(
+                        "Skipping {:?} in {:?} due to filter",
+                        owners_file,
+                        self.path
+                    );
+                    false
+                }
+            });
+        let Some(owners_file) = owners_files.next() else {
             return Ok(false);
-        }
-        if !allow_filter.allowed(&owners_file) {
-            trace!(
-                "Skipping {:?} in {:?} due to filter",
-                owners_file,
-                self.path
+        };
+        let ignored_owners_files = owners_files.collect::<Vec<_>>();
+        if !ignored_owners_files.is_empty() {
+            warn!(
+                "Found multiple OWNERS files in {:?}. Using {:?}, ignoring {:?}",
+                self.path, owners_file, ignored_owners_files
             );
-            return Ok(false);
         }
 
-        debug!("Parsing {:?}", &owners_file);
-        let owners_config = OwnersFileConfig::from_file(owners_file, &self.repo_base)?;
+        let (owners_config, dependencies) = match options.cache {
+            Some(cache) => {
+                cache.parse_with_dependencies(options.source, &owners_file, &self.repo_base)?
+            }
+            None => {
+                debug!("Parsing {:?}", &owners_file);
+                OwnersFileConfig::from_file_with_dependencies(
+                    options.source,
+                    &owners_file,
+                    &self.repo_base,
+                )?
+            }
+        };
         self.owners_config = owners_config;
+        self.includes = dependencies.included_by;
+        self.owners_file = Some(owners_file);
 
         Ok(true)
     }
 
-    pub fn load_from_files<P, F>(root: P, allow_filter: &F) -> anyhow::Result<TreeNode>
+    /// Loads the tree of OWNERS files under `root`.
+    ///
+    /// When the allow filter knows all allowed OWNERS files up front, the tree is built directly
+    /// from them. Otherwise, the file tree is walked in parallel. Either way, children are sorted by
+    /// path, so the result is deterministic.
+    pub fn load_from_files<P, F, S>(
+        root: P,
+        options: &LoadOptions<F, S>,
+    ) -> anyhow::Result<TreeNode>
     where
         P: AsRef<Path>,
         F: AllowFilter,
+        S: AsRef<str> + Sync,
     {
-        let mut root_node = TreeNode::new(&root, &root);
-        root_node.maybe_load_owners_file(allow_filter)?;
-        for entry in fs::read_dir(root)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() &&
-                // Don't process file tree branches with no allowed files
-                allow_filter.allowed(&path)
-            {
-                root_node.load_children_from_files(&path, allow_filter)?;
+        let root = options.source.canonicalize(root.as_ref())?;
+        let submodule_paths = options
+            .source
+            .submodule_paths(&root)
+            .unwrap_or_else(|error| {
+                debug!("Not checking git for submodules: {}", error);
+                HashSet::new()
+            });
+        let context = LoadContext {
+            options,
+            submodule_paths,
+        };
+        let mut root_node = TreeNode::new(&root, &root, options.source)?;
+        root_node.maybe_load_owners_file(options)?;
+        let loaded = match options.allow_filter.allowed_owners_files() {
+            Some(owners_files) => load_from_owners_files(&root, owners_files, &context)?,
+            None => walk_subdirectories(&root, &root, &context)?,
+        };
+        root_node.children = loaded.children;
+        root_node.opaque_submodules = loaded.opaque_submodules;
+        Ok(root_node)
+    }
+}
+
+/// Nodes loaded from part of the file tree, to be attached to the nearest ancestor with an OWNERS
+/// file.
+#[derive(Default)]
+struct LoadedNodes {
+    children: Vec<TreeNode>,
+    opaque_submodules: Vec<PathBuf>,
+}
+
+impl LoadedNodes {
+    fn attach_to(self, mut node: TreeNode) -> LoadedNodes {
+        node.children = self.children;
+        node.opaque_submodules = self.opaque_submodules;
+        LoadedNodes {
+            children: vec![node],
+            ..LoadedNodes::default()
+        }
+    }
+}
+
+/// Builds the tree below `root` from the allowed OWNERS files, without walking directories.
+fn load_from_owners_files<F, S>(
+    root: &Path,
+    owners_files: &HashSet<PathBuf>,
+    context: &LoadContext<F, S>,
+) -> anyhow::Result<LoadedNodes>
+where
+    F: AllowFilter,
+    S: AsRef<str> + Sync,
+{
+    // NB: Paths sort component-wise, so each directory comes directly after its closest ancestor,
+    // or that ancestor's other descendants.
+    let mut owners_directories = BTreeMap::new();
+    for owners_file in owners_files {
+        let Some(directory) = owners_file.parent() else {
+            continue;
+        };
+        if directory == root || !directory.starts_with(root) {
+            continue;
+        }
+        if let Some(repo_base) = context.reachable_repo_base(root, directory) {
+            owners_directories.insert(directory.to_path_buf(), repo_base);
+        }
+    }
+    let mut opaque_submodules = Vec::new();
+    if context.options.submodule_mode == SubmoduleMode::Opaque {
+        for submodule in &context.submodule_paths {
+            let reachable = submodule.parent().map_or(false, |parent| {
+                parent == root || context.reachable_repo_base(root, parent).is_some()
+            });
+            if submodule != root && submodule.starts_with(root) && reachable {
+                opaque_submodules.push(submodule.clone());
             }
         }
-        Ok(root_node)
+        opaque_submodules.sort();
     }
 
-    fn load_children_from_files<F>(
-        &mut self,
-        directory: &Path,
-        allow_filter: &F,
-    ) -> anyhow::Result<()>
-    where
-        F: AllowFilter,
+    let nodes = owners_directories
+        .into_par_iter()
+        .map(|(directory, repo_base)| {
+            let mut node = TreeNode {
+                path: directory,
+                repo_base,
+                ..TreeNode::default()
+            };
+            let has_owners_file = node.maybe_load_owners_file(context.options)?;
+            Ok(has_owners_file.then_some(node))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Each node is attached to the closest ancestor with an OWNERS file, or the root.
+    let mut stack = vec![TreeNode {
+        path: root.to_path_buf(),
+        ..TreeNode::default()
+    }];
+    let mut opaque_submodules = opaque_submodules.into_iter().peekable();
+    for node in nodes.into_iter().flatten() {
+        while let Some(submodule) = opaque_submodules.next_if(|submodule| *submodule < node.path) {
+            pop_non_ancestors(&mut stack, &submodule);
+            stack.last_mut().unwrap().opaque_submodules.push(submodule);
+        }
+        pop_non_ancestors(&mut stack, &node.path);
+        stack.push(node);
+    }
+    for submodule in opaque_submodules {
+        pop_non_ancestors(&mut stack, &submodule);
+        stack.last_mut().unwrap().opaque_submodules.push(submodule);
+    }
+    pop_non_ancestors(&mut stack, root);
+    let root_node = stack.pop().unwrap();
+    Ok(LoadedNodes {
+        children: root_node.children,
+        opaque_submodules: root_node.opaque_submodules,
+    })
+}
+
+/// Completes the nodes on the stack which aren't ancestors of `path`, attaching each to its parent.
+fn pop_non_ancestors(stack: &mut Vec<TreeNode>, path: &Path) {
+    while stack.len() > 1 && !path.starts_with(&stack.last().unwrap().path) {
+        let node = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(node);
+    }
+}
+
+/// Walks the subdirectories of `directory` in parallel.
+fn walk_subdirectories<F, S>(
+    directory: &Path,
+    repo_base: &Path,
+    context: &LoadContext<F, S>,
+) -> anyhow::Result<LoadedNodes>
+where
+    F: AllowFilter,
+    S: AsRef<str> + Sync,
+{
+    let subdirectories = context.options.source.subdirectories(directory)?;
+    let results = subdirectories
+        .par_iter()
+        .map(|subdirectory| walk_directory(subdirectory, repo_base, context))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut loaded = LoadedNodes::default();
+    for result in results {
+        loaded.children.extend(result.children);
+        loaded.opaque_submodules.extend(result.opaque_submodules);
+    }
+    loaded.children.sort_by(|a, b| a.path.cmp(&b.path));
+    loaded.opaque_submodules.sort();
+    Ok(loaded)
+}
+
+fn walk_directory<F, S>(
+    directory: &Path,
+    repo_base: &Path,
+    context: &LoadContext<F, S>,
+) -> anyhow::Result<LoadedNodes>
+where
+    F: AllowFilter,
+    S: AsRef<str> + Sync,
+{
+    if directory.file_name() == Some(OsStr::new(".git")) {
+        // Don't process git metadata
+        return Ok(LoadedNodes::default());
+    }
+    let mut repo_base = repo_base;
+    // NB: Submodule directories need checking first, as they typically aren't allowed.
+    if context.is_submodule(directory) {
+        match context.options.submodule_mode {
+            SubmoduleMode::Ignore => {
+                debug!("Ignoring submodule {:?}", directory);
+                return Ok(LoadedNodes::default());
+            }
+            SubmoduleMode::Opaque => {
+                return Ok(LoadedNodes {
+                    opaque_submodules: vec![directory.to_path_buf()],
+                    ..LoadedNodes::default()
+                });
+            }
+            SubmoduleMode::Recurse => repo_base = directory,
+        }
+    } else if !context
+        .options
+        .allow_filter
+        .allowed_in_walk(directory, true)
     {
-        if directory.file_name().unwrap() == ".git" {
-            // Don't process git metadata
-            return Ok(());
+        // Don't process file tree branches with no allowed files
+        return Ok(LoadedNodes::default());
+    }
+
+    let mut node = TreeNode {
+        path: directory.to_path_buf(),
+        repo_base: repo_base.to_path_buf(),
+        ..TreeNode::default()
+    };
+    let has_owners_file = node.maybe_load_owners_file(context.options)?;
+    let loaded = walk_subdirectories(directory, repo_base, context)?;
+    if has_owners_file {
+        Ok(loaded.attach_to(node))
+    } else {
+        Ok(loaded)
+    }
+}
+
+/// How git submodules are handled when loading the tree.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmoduleMode {
+    /// Skip submodules entirely.
+    #[default]
+    Ignore,
+    /// Treat each submodule as a single path, owned by the enclosing directory's owners.
+    Opaque,
+    /// Load the submodule's own OWNERS files, as though it were a directory of the superproject.
+    Recurse,
+}
+
+impl FromStr for SubmoduleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<SubmoduleMode> {
+        match text {
+            "ignore" => Ok(SubmoduleMode::Ignore),
+            "opaque" => Ok(SubmoduleMode::Opaque),
+            "recurse" => Ok(SubmoduleMode::Recurse),
+            _ => Err(anyhow!(
+                "Invalid submodule mode '{}'. Expected 'ignore', 'opaque' or 'recurse'.",
+                text
+            )),
         }
-        let mut current_loc_node = TreeNode::new(directory, &self.repo_base);
-        let has_current_owners_file = current_loc_node.maybe_load_owners_file(allow_filter)?;
-        for entry in fs::read_dir(directory)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                if has_current_owners_file {
-                    current_loc_node.load_children_from_files(&path, allow_filter)?;
-                } else {
-                    self.load_children_from_files(&path, allow_filter)?;
+    }
+}
+
+/// Settings for loading the tree of OWNERS files.
+pub struct LoadOptions<'a, F, S> {
+    pub allow_filter: &'a F,
+    /// Accepted OWNERS file names, in order of precedence.
+    pub owners_file_names: &'a [S],
+    pub submodule_mode: SubmoduleMode,
+    /// Where files are read from, e.g., the working tree.
+    pub source: &'a dyn FileSource,
+    /// Cache of parsed OWNERS files. Files are always parsed when not set.
+    pub cache: Option<&'a ParseCache>,
+}
+
+/// State shared across the whole walk of the file tree.
+struct LoadContext<'a, F, S> {
+    options: &'a LoadOptions<'a, F, S>,
+    /// Submodules registered in the git index, including those of checked out submodules, which may
+    /// not be checked out themselves.
+    submodule_paths: HashSet<PathBuf>,
+}
+
+impl<'a, F, S> LoadContext<'a, F, S> {
+    /// Whether the canonical `directory` is a submodule. Other nested repositories, e.g., vendored
+    /// checkouts, are ordinary directories.
+    fn is_submodule(&self, directory: &Path) -> bool {
+        self.submodule_paths.contains(directory)
+    }
+
+    /// The repo base for `directory`, which is below `root`, or `None` if walking the file tree
+    /// wouldn't reach it, due to git metadata or submodules.
+    fn reachable_repo_base(&self, root: &Path, directory: &Path) -> Option<PathBuf> {
+        let mut repo_base = root.to_path_buf();
+        let mut current = root.to_path_buf();
+        for component in directory.strip_prefix(root).ok()? {
+            if component == ".git" {
+                return None;
+            }
+            current.push(component);
+            if self.is_submodule(&current) {
+                if self.options.submodule_mode != SubmoduleMode::Recurse {
+                    return None;
                 }
+                repo_base = current.clone();
             }
         }
-        if has_current_owners_file {
-            self.children.push(current_loc_node);
-        }
-        Ok(())
+        Some(repo_base)
     }
 }
 
 #[cfg(test)]
+// The expected trees are written out in full, which these lints flag.
+#[allow(
+    clippy::redundant_clone,
+    clippy::needless_borrow,
+    clippy::needless_update
+)]
 mod tests {
-    use crate::allow_filter::FilterGitMetadata;
+    use crate::allow_filter::{AllowList, FilterGitMetadata};
+    use crate::file_source::{InMemoryFiles, WorkingTree};
     use crate::owners_file::OwnersFileConfig;
     use crate::owners_set::OwnersSet;
-    use crate::owners_tree::{OwnersTree, TreeNode};
-    use crate::test_utils::create_test_file;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode, TreeNode};
+    use crate::test_utils::{add_gitlink, create_test_file};
+    use git2::Repository;
     use indoc::indoc;
-    use std::collections::HashMap;
     use std::collections::HashSet;
-    use tempfile::tempdir;
+    use std::collections::{BTreeMap, HashMap};
+    use std::path::{Path, PathBuf};
+    use tempfile::{tempdir, TempDir};
 
     const ALLOW_ANY: FilterGitMetadata = FilterGitMetadata {};
+    const OWNERS_FILE_NAMES: [&str; 1] = ["OWNERS"];
+    const LOAD_OPTIONS: LoadOptions<FilterGitMetadata, &str> = LoadOptions {
+        allow_filter: &ALLOW_ANY,
+        owners_file_names: &OWNERS_FILE_NAMES,
+        submodule_mode: SubmoduleMode::Ignore,
+        source: &WorkingTree {},
+        cache: None,
+    };
 
     #[test]
     fn single_file_at_root() -> anyhow::Result<()> {
@@ -135,10 +442,12 @@ mod tests {
             },
         )?;
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY)?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
         let expected = TreeNode {
             path: temp_dir_path.to_path_buf(),
             repo_base: temp_dir_path.to_path_buf(),
+            owners_file: Some(temp_dir_path.join("OWNERS")),
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     owners: vec![
@@ -173,13 +482,15 @@ mod tests {
             },
         )?;
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY)?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
         let expected = TreeNode {
             path: temp_dir_path.to_path_buf(),
             repo_base: temp_dir_path.to_path_buf(),
             children: vec![TreeNode {
                 path: temp_dir_path.join("subdir").to_path_buf(),
                 repo_base: temp_dir_path.to_path_buf(),
+                owners_file: Some(temp_dir_path.join("subdir/OWNERS")),
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         owners: vec![
@@ -224,10 +535,13 @@ mod tests {
             },
         )?;
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY)?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
         let expected = TreeNode {
             path: temp_dir_path.to_path_buf(),
             repo_base: temp_dir_path.to_path_buf(),
+            owners_file: Some(temp_dir_path.join("OWNERS")),
+            includes: BTreeMap::new(),
+            opaque_submodules: vec![],
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     owners: vec!["ada.lovelace".to_string(), "grace.hopper".to_string()]
@@ -240,6 +554,8 @@ mod tests {
             children: vec![TreeNode {
                 path: temp_dir_path.join("subdir/foo").to_path_buf(),
                 repo_base: temp_dir_path.to_path_buf(),
+                owners_file: Some(temp_dir_path.join("subdir/foo/OWNERS")),
+                includes: BTreeMap::new(),
                 owners_config: OwnersFileConfig {
                     all_files: OwnersSet {
                         owners: vec![
@@ -260,6 +576,50 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn in_memory_files() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/bar/OWNERS", "include /shared/OWNERS\n"),
+            ("/repo/shared/OWNERS", "grace.hopper\n"),
+        ]);
+        let root = PathBuf::from("/repo");
+        let tree = OwnersTree::load_from_files(
+            &root,
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+        )?;
+
+        assert_eq!(
+            tree.children
+                .iter()
+                .map(|child| child.path.clone())
+                .collect::<Vec<_>>(),
+            vec![root.join("foo/bar"), root.join("shared")]
+        );
+        assert_eq!(
+            tree.children[0].owners_config.all_files.owners,
+            HashSet::from(["grace.hopper".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_root() {
+        let files = InMemoryFiles::new([("/repo/OWNERS", "ada.lovelace\n")]);
+        assert!(TreeNode::new("/missing", "/missing", &files).is_err());
+        assert!(OwnersTree::load_from_files(
+            "/missing",
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+        )
+        .is_err());
+    }
+
     #[test]
     fn ignore_hidden_files() -> anyhow::Result<()> {
         let temp_dir = tempdir()?;
@@ -282,10 +642,12 @@ mod tests {
             },
         )?;
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY)?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
         let expected = TreeNode {
             path: temp_dir_path.to_path_buf(),
             repo_base: temp_dir_path.to_path_buf(),
+            owners_file: Some(temp_dir_path.join("OWNERS")),
+            includes: BTreeMap::new(),
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     owners: vec!["ada.lovelace".to_string(), "grace.hopper".to_string()]
@@ -344,10 +706,13 @@ mod tests {
             },
         )?;
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let mut tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY)?;
+        let mut tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
         let mut expected = TreeNode {
             path: temp_dir_path.to_path_buf(),
             repo_base: temp_dir_path.to_path_buf(),
+            owners_file: Some(temp_dir_path.join("OWNERS")),
+            includes: BTreeMap::new(),
+            opaque_submodules: vec![],
             owners_config: OwnersFileConfig {
                 all_files: OwnersSet {
                     owners: vec!["ada.lovelace".to_string(), "grace.hopper".to_string()]
@@ -361,6 +726,17 @@ mod tests {
                 TreeNode {
                     path: temp_dir_path.join("subdir/foo").to_path_buf(),
                     repo_base: temp_dir_path.to_path_buf(),
+                    owners_file: Some(temp_dir_path.join("subdir/foo/OWNERS")),
+                    includes: BTreeMap::from([
+                        (
+                            temp_dir_path.join("subdir/bar/OWNERS"),
+                            temp_dir_path.join("subdir/foo/OWNERS"),
+                        ),
+                        (
+                            temp_dir_path.join("subdir/baz/OWNERS"),
+                            temp_dir_path.join("subdir/foo/OWNERS"),
+                        ),
+                    ]),
                     owners_config: OwnersFileConfig {
                         all_files: OwnersSet {
                             owners: vec![
@@ -388,6 +764,8 @@ mod tests {
                 TreeNode {
                     path: temp_dir_path.join("subdir/bar").to_path_buf(),
                     repo_base: temp_dir_path.to_path_buf(),
+                    owners_file: Some(temp_dir_path.join("subdir/bar/OWNERS")),
+                    includes: BTreeMap::new(),
                     owners_config: OwnersFileConfig {
                         all_files: OwnersSet {
                             owners: vec!["mary.jackson".to_string()]
@@ -402,6 +780,8 @@ mod tests {
                 TreeNode {
                     path: temp_dir_path.join("subdir/baz").to_path_buf(),
                     repo_base: temp_dir_path.to_path_buf(),
+                    owners_file: Some(temp_dir_path.join("subdir/baz/OWNERS")),
+                    includes: BTreeMap::new(),
                     owners_config: OwnersFileConfig {
                         all_files: OwnersSet {
                             owners: vec![].into_iter().collect::<HashSet<String>>(),
@@ -460,7 +840,7 @@ mod tests {
         )?;
 
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY);
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS);
 
         assert!(tree.is_err());
         Ok(())
@@ -490,15 +870,819 @@ mod tests {
             &temp_dir,
             "subdir/bar/OWNERS",
             indoc! {"\
+                set inherit = false
+                mary.jackson
+
+                [*.py]
+                set inherit = false
+                alan.turing
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+        let expected = OwnersFileConfig {
+            all_files: OwnersSet {
+                inherit: Some(false),
+                owners: vec!["mary.jackson".to_string()]
+                    .into_iter()
+                    .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
+            },
+            pattern_overrides: HashMap::from([(
+                "*.py".to_string(),
+                OwnersSet {
+                    inherit: Some(false),
+                    owners: vec!["alan.turing".to_string()]
+                        .into_iter()
+                        .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
+                },
+            )]),
+        };
+
+        assert_eq!(foo.owners_config, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_set_statement_nested() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                include /subdir/bar/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/bar/OWNERS",
+            indoc! {"\
+                include /subdir/baz/OWNERS
+                mary.jackson
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/baz/OWNERS",
+            indoc! {"\
+                set inherit = false
+                alan.turing
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+        let expected = OwnersSet {
+            inherit: Some(false),
+            owners: vec!["alan.turing".to_string(), "mary.jackson".to_string()]
+                .into_iter()
+                .collect::<HashSet<String>>(),
+            expiries: BTreeMap::new(),
+        };
+
+        assert_eq!(foo.owners_config.all_files, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_set_statement_explicit_wins() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                include /subdir/bar/OWNERS
+                include /subdir/baz/OWNERS
+                set inherit = true
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/bar/OWNERS",
+            indoc! {"\
+                set inherit = false
+                mary.jackson
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/baz/OWNERS",
+            indoc! {"\
+                set inherit = true
+                alan.turing
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+
+        assert_eq!(foo.owners_config.all_files.inherit, Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_set_statement_conflict() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                include /subdir/bar/OWNERS
+                include /subdir/baz/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/bar/OWNERS",
+            indoc! {"\
+                [*.py]
+                set inherit = false
+                mary.jackson
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/baz/OWNERS",
+            indoc! {"\
+                include /subdir/qux/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "subdir/qux/OWNERS",
+            indoc! {"\
+                [*.py]
                 set inherit = true
+                alan.turing
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS);
+
+        assert!(tree.is_err());
+        assert!(tree
+            .unwrap_err()
+            .to_string()
+            .contains("Conflicting inherit values for [*.py]"));
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_in_pattern_section() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                margaret.hamilton
+
+                [*.py]
+                katherine.johnson
+                include /python/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "python/OWNERS",
+            indoc! {"\
+                set inherit = false
+                guido.van.rossum
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+        let expected = OwnersFileConfig {
+            all_files: OwnersSet {
+                inherit: None,
+                owners: vec!["margaret.hamilton".to_string()]
+                    .into_iter()
+                    .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
+            },
+            pattern_overrides: HashMap::from([(
+                "*.py".to_string(),
+                OwnersSet {
+                    inherit: Some(false),
+                    owners: vec![
+                        "katherine.johnson".to_string(),
+                        "guido.van.rossum".to_string(),
+                    ]
+                    .into_iter()
+                    .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
+                },
+            )]),
+        };
+
+        assert_eq!(foo.owners_config, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_in_pattern_section_with_patterns() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                [*.py]
+                include /python/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "python/OWNERS",
+            indoc! {"\
+                guido.van.rossum
+
+                [tests/*]
+                alan.turing
                 "
             },
         )?;
 
         let temp_dir_path = temp_dir.path().canonicalize()?;
-        let tree = OwnersTree::load_from_files(&temp_dir_path, &ALLOW_ANY);
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS);
 
         assert!(tree.is_err());
+
+        // Selecting a single section doesn't help, but selecting only the owners does
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                [*.py]
+                include /python/OWNERS [ tests/* ]
+                "
+            },
+        )?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS);
+        assert!(tree
+            .unwrap_err()
+            .to_string()
+            .contains("Use ':owners-only' instead."));
+
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                [*.py]
+                include /python/OWNERS :owners-only
+                "
+            },
+        )?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+        assert_eq!(
+            foo.owners_config.pattern_overrides["*.py"].owners,
+            HashSet::from(["guido.van.rossum".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_selected_sections() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                include /db/OWNERS [*.sql]
+                include /docs/OWNERS :owners-only
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "db/OWNERS",
+            indoc! {"\
+                edgar.codd
+
+                [*.sql]
+                donald.chamberlin
+
+                [*.py]
+                guido.van.rossum
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "docs/OWNERS",
+            indoc! {"\
+                donald.knuth
+
+                [*.md]
+                john.gruber
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let foo = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("subdir/foo"))
+            .unwrap();
+        let expected = OwnersFileConfig {
+            all_files: OwnersSet {
+                inherit: None,
+                owners: vec!["donald.knuth".to_string()]
+                    .into_iter()
+                    .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
+            },
+            pattern_overrides: HashMap::from([(
+                "*.sql".to_string(),
+                OwnersSet {
+                    inherit: None,
+                    owners: vec!["donald.chamberlin".to_string()]
+                        .into_iter()
+                        .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
+                },
+            )]),
+        };
+
+        assert_eq!(foo.owners_config, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_selected_section_missing() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "subdir/foo/OWNERS",
+            indoc! {"\
+                include /db/OWNERS [*.rs]
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "db/OWNERS",
+            indoc! {"\
+                [*.sql]
+                donald.chamberlin
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(temp_dir_path, &LOAD_OPTIONS);
+
+        assert!(tree.is_err());
+        assert!(tree
+            .unwrap_err()
+            .to_string()
+            .contains("Selected section [*.rs] not found"));
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_glob() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "service/OWNERS",
+            indoc! {"\
+                include /shared/policies/*/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "shared/policies/security/OWNERS",
+            indoc! {"\
+                [*.key]
+                set inherit = false
+                whitfield.diffie
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "shared/policies/release/OWNERS",
+            indoc! {"\
+                margaret.hamilton
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let service = tree
+            .children
+            .iter()
+            .find(|child| child.path == temp_dir_path.join("service"))
+            .unwrap();
+        let expected = OwnersFileConfig {
+            all_files: OwnersSet {
+                inherit: None,
+                owners: vec!["margaret.hamilton".to_string()]
+                    .into_iter()
+                    .collect::<HashSet<String>>(),
+                expiries: BTreeMap::new(),
+            },
+            pattern_overrides: HashMap::from([(
+                "*.key".to_string(),
+                OwnersSet {
+                    inherit: Some(false),
+                    owners: vec!["whitfield.diffie".to_string()]
+                        .into_iter()
+                        .collect::<HashSet<String>>(),
+                    expiries: BTreeMap::new(),
+                },
+            )]),
+        };
+
+        assert_eq!(service.owners_config, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_glob_no_matches() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "service/OWNERS",
+            indoc! {"\
+                include /shared/policies/*/OWNERS
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(temp_dir_path, &LOAD_OPTIONS);
+
+        assert!(tree.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn included_file_glob_circular_include() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "shared/policies/security/OWNERS",
+            indoc! {"\
+                include ../*/OWNERS
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "shared/policies/release/OWNERS",
+            indoc! {"\
+                margaret.hamilton
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(temp_dir_path, &LOAD_OPTIONS);
+
+        assert!(tree.is_err());
+        assert!(tree
+            .unwrap_err()
+            .to_string()
+            .contains("Cycle detected in includes"));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_owners_file_names() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            ".owners",
+            indoc! {"\
+                ada.lovelace
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "vendor/lib/OWNERS.md",
+            indoc! {"\
+                grace.hopper
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "vendor/lib/.owners",
+            indoc! {"\
+                margaret.hamilton
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "other/OWNERS",
+            indoc! {"\
+                katherine.johnson
+                "
+            },
+        )?;
+
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let tree = OwnersTree::load_from_files(
+            &temp_dir_path,
+            &LoadOptions {
+                owners_file_names: &[".owners", "OWNERS.md"],
+                ..LOAD_OPTIONS
+            },
+        )?;
+        let expected = TreeNode {
+            path: temp_dir_path.clone(),
+            repo_base: temp_dir_path.clone(),
+            owners_file: Some(temp_dir_path.join(".owners")),
+            includes: BTreeMap::new(),
+            opaque_submodules: vec![],
+            owners_config: OwnersFileConfig {
+                all_files: OwnersSet {
+                    owners: vec!["ada.lovelace".to_string()]
+                        .into_iter()
+                        .collect::<HashSet<String>>(),
+                    ..OwnersSet::default()
+                },
+                ..OwnersFileConfig::default()
+            },
+            children: vec![TreeNode {
+                path: temp_dir_path.join("vendor/lib"),
+                repo_base: temp_dir_path.clone(),
+                owners_file: Some(temp_dir_path.join("vendor/lib/.owners")),
+                includes: BTreeMap::new(),
+                owners_config: OwnersFileConfig {
+                    all_files: OwnersSet {
+                        owners: vec!["margaret.hamilton".to_string()]
+                            .into_iter()
+                            .collect::<HashSet<String>>(),
+                        ..OwnersSet::default()
+                    },
+                    ..OwnersFileConfig::default()
+                },
+                ..TreeNode::default()
+            }],
+        };
+
+        assert_eq!(tree, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn submodule_modes() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(
+            &temp_dir,
+            "vendor/sub/.git",
+            "gitdir: ../../.git/modules/sub\n",
+        )?;
+        create_test_file(
+            &temp_dir,
+            "vendor/sub/OWNERS",
+            indoc! {"\
+                grace.hopper
+                include /.github/TEAM
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "vendor/sub/.github/TEAM", "margaret.hamilton\n")?;
+        add_gitlink(&repo, "vendor/sub")?;
+        // A nested repository which isn't a submodule is an ordinary directory
+        Repository::init(temp_dir.path().join("vendor/checkout"))?;
+        create_test_file(&temp_dir, "vendor/checkout/OWNERS", "alan.turing\n")?;
+        let temp_dir_path = temp_dir.path().canonicalize()?;
+        let submodule_path = temp_dir_path.join("vendor/sub");
+        let checkout_path = temp_dir_path.join("vendor/checkout");
+
+        let tree = OwnersTree::load_from_files(&temp_dir_path, &LOAD_OPTIONS)?;
+        let children = tree
+            .children
+            .iter()
+            .map(|child| child.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(children, vec![checkout_path.clone()]);
+        assert!(tree.opaque_submodules.is_empty());
+
+        let tree = OwnersTree::load_from_files(
+            &temp_dir_path,
+            &LoadOptions {
+                submodule_mode: SubmoduleMode::Opaque,
+                ..LOAD_OPTIONS
+            },
+        )?;
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.opaque_submodules, vec![submodule_path.clone()]);
+
+        let tree = OwnersTree::load_from_files(
+            &temp_dir_path,
+            &LoadOptions {
+                submodule_mode: SubmoduleMode::Recurse,
+                ..LOAD_OPTIONS
+            },
+        )?;
+        assert!(tree.opaque_submodules.is_empty());
+        let expected_children = vec![TreeNode {
+            path: submodule_path.clone(),
+            owners_file: Some(submodule_path.join("OWNERS")),
+            includes: BTreeMap::from([(
+                submodule_path.join(".github/TEAM"),
+                submodule_path.join("OWNERS"),
+            )]),
+            // Includes are resolved relative to the submodule
+            repo_base: submodule_path,
+            owners_config: OwnersFileConfig {
+                all_files: OwnersSet {
+                    owners: vec!["grace.hopper".to_string(), "margaret.hamilton".to_string()]
+                        .into_iter()
+                        .collect::<HashSet<String>>(),
+                    ..OwnersSet::default()
+                },
+                ..OwnersFileConfig::default()
+            },
+            ..TreeNode::default()
+        }];
+        assert_eq!(tree.children[0].path, checkout_path);
+        assert_eq!(tree.children[1..], expected_children);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_submodule_mode() {
+        assert_eq!(
+            "opaque".parse::<SubmoduleMode>().unwrap(),
+            SubmoduleMode::Opaque
+        );
+        assert!("flatten".parse::<SubmoduleMode>().is_err());
+    }
+
+    /// Creates OWNERS files at each of the given paths, returning their absolute paths.
+    fn create_owners_files(
+        temp_dir: &TempDir,
+        paths: &[String],
+    ) -> anyhow::Result<HashSet<PathBuf>> {
+        let mut owners_files = HashSet::new();
+        for (i, path) in paths.iter().enumerate() {
+            let contents = if i % 3 == 0 {
+                format!("user{i}\n\n[*.rs]\nset inherit = false\nrust{i}\n")
+            } else {
+                format!("user{i}\n")
+            };
+            create_test_file(temp_dir, path, &contents)?;
+            owners_files.insert(temp_dir.path().join(path));
+        }
+        Ok(owners_files)
+    }
+
+    fn assert_allow_list_matches_walk(
+        root: &Path,
+        owners_files: HashSet<PathBuf>,
+        submodule_mode: SubmoduleMode,
+    ) -> anyhow::Result<TreeNode> {
+        let allow_list = AllowList::from(owners_files, true, &OWNERS_FILE_NAMES)?;
+        let walked = OwnersTree::load_from_files(
+            root,
+            &LoadOptions {
+                submodule_mode,
+                ..LOAD_OPTIONS
+            },
+        )?;
+        let built = OwnersTree::load_from_files(
+            root,
+            &LoadOptions {
+                allow_filter: &allow_list,
+                owners_file_names: &OWNERS_FILE_NAMES,
+                submodule_mode,
+                source: &WorkingTree {},
+                cache: None,
+            },
+        )?;
+        assert_eq!(built, walked);
+        Ok(built)
+    }
+
+    #[test]
+    fn allow_list_matches_walk() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        let paths = [
+            "OWNERS",
+            "a/OWNERS",
+            "a/b/c/OWNERS",
+            "a/b/d/OWNERS",
+            "a-b/OWNERS",
+            "b/c/OWNERS",
+            "b/c/d/e/OWNERS",
+            "vendor/sub/OWNERS",
+            "vendor/sub/lib/OWNERS",
+        ]
+        .map(String::from);
+        let owners_files = create_owners_files(&temp_dir, &paths)?;
+        create_test_file(
+            &temp_dir,
+            "vendor/sub/.git",
+            "gitdir: ../../.git/modules/sub\n",
+        )?;
+        add_gitlink(&repo, "vendor/sub")?;
+        let root = temp_dir.path().canonicalize()?;
+
+        let tree =
+            assert_allow_list_matches_walk(&root, owners_files.clone(), SubmoduleMode::Ignore)?;
+        assert_eq!(
+            tree.children
+                .iter()
+                .map(|child| child.path.clone())
+                .collect::<Vec<_>>(),
+            vec![root.join("a"), root.join("a-b"), root.join("b/c")]
+        );
+        let tree = assert_allow_list_matches_walk(&root, owners_files, SubmoduleMode::Recurse)?;
+        assert_eq!(tree.children[3].repo_base, root.join("vendor/sub"));
+        assert_eq!(
+            tree.children[3].children[0].repo_base,
+            root.join("vendor/sub")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn allow_list_opaque_submodule() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "docs/OWNERS", "grace.hopper\n")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("OWNERS"))?;
+        index.add_path(Path::new("docs/OWNERS"))?;
+        index.write()?;
+        // Not checked out, and with no tracked OWNERS files alongside it
+        add_gitlink(&repo, "vendor/sub")?;
+        add_gitlink(&repo, "docs/sub")?;
+        let root = temp_dir.path().canonicalize()?;
+
+        let allow_list = AllowList::allow_git_files(&root, &OWNERS_FILE_NAMES, false)?;
+        let tree = OwnersTree::load_from_files(
+            &root,
+            &LoadOptions {
+                allow_filter: &allow_list,
+                owners_file_names: &OWNERS_FILE_NAMES,
+                submodule_mode: SubmoduleMode::Opaque,
+                source: &WorkingTree {},
+                cache: None,
+            },
+        )?;
+        assert_eq!(tree.opaque_submodules, vec![root.join("vendor/sub")]);
+        assert_eq!(
+            tree.children[0].opaque_submodules,
+            vec![root.join("docs/sub")]
+        );
         Ok(())
     }
 }
@@ -0,0 +1,482 @@
+use crate::codeowners::{to_codeowners_rules, CodeownersMatcher};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The owners of each file, by path relative to the repo root.
+pub type FileOwners = BTreeMap<PathBuf, BTreeSet<String>>;
+
+/// Owners added to, and removed from, some files.
+#[derive(Serialize, PartialEq, Eq, Debug, Default, Clone)]
+pub struct OwnershipChange {
+    pub added: BTreeSet<String>,
+    pub removed: BTreeSet<String>,
+}
+
+impl OwnershipChange {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A change to the ownership of a file, or of every file in a directory, in which case the path
+/// ends with `/`.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct PathChange {
+    pub path: String,
+    /// The number of files in the directory with the change.
+    pub files: usize,
+    #[serde(flatten)]
+    pub change: OwnershipChange,
+}
+
+/// Resolves the owners of each of `files`, relative to the repo root, from the generated
+/// CODEOWNERS rules.
+pub fn file_owners<I, P>(
+    codeowners: &HashMap<String, HashSet<String>>,
+    files: I,
+) -> anyhow::Result<FileOwners>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let matcher = CodeownersMatcher::new(to_codeowners_rules(codeowners))?;
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let owners = matcher.owners(&file).iter().cloned().collect();
+            (file.as_ref().to_path_buf(), owners)
+        })
+        .collect())
+}
+
+/// Compares the owners of files present both `before` and `after`, returning the files whose
+/// owners changed. With `only_lost_owners`, only removed owners are considered.
+pub fn diff_file_owners(
+    before: &FileOwners,
+    after: &FileOwners,
+    only_lost_owners: bool,
+) -> BTreeMap<PathBuf, OwnershipChange> {
+    before
+        .iter()
+        .filter_map(|(file, before_owners)| {
+            let after_owners = after.get(file)?;
+            let change = OwnershipChange {
+                added: if only_lost_owners {
+                    BTreeSet::new()
+                } else {
+                    after_owners.difference(before_owners).cloned().collect()
+                },
+                removed: before_owners.difference(after_owners).cloned().collect(),
+            };
+            (!change.is_empty()).then(|| (file.clone(), change))
+        })
+        .collect()
+}
+
+/// Files present `after` but not `before`, e.g., new or moved files, as a change from no owners to
+/// their owners `after`.
+pub fn added_files(before: &FileOwners, after: &FileOwners) -> BTreeMap<PathBuf, OwnershipChange> {
+    after
+        .iter()
+        .filter(|(file, _)| !before.contains_key(*file))
+        .map(|(file, owners)| {
+            let change = OwnershipChange {
+                added: owners.clone(),
+                ..OwnershipChange::default()
+            };
+            (file.clone(), change)
+        })
+        .collect()
+}
+
+/// Files present `before` but not `after`, e.g., deleted or moved files, as a change from their
+/// owners `before` to no owners. Moves can't be told apart from deletions, so a move which loses
+/// owners shows up here.
+pub fn removed_files(
+    before: &FileOwners,
+    after: &FileOwners,
+) -> BTreeMap<PathBuf, OwnershipChange> {
+    added_files(after, before)
+        .into_iter()
+        .map(|(file, change)| {
+            let change = OwnershipChange {
+                removed: change.added,
+                ..OwnershipChange::default()
+            };
+            (file, change)
+        })
+        .collect()
+}
+
+/// Whether every compared file in a directory has the same change, including no change.
+enum DirectoryChange<'a> {
+    Uniform(Option<&'a OwnershipChange>),
+    Mixed,
+}
+
+/// Reports the `changes` to each file, or collapses them into the highest directory in which every
+/// one of the `compared` files has the same change.
+pub fn collapse_changes<'a, I>(
+    changes: &BTreeMap<PathBuf, OwnershipChange>,
+    compared: I,
+) -> Vec<PathChange>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    let mut directories: HashMap<&Path, DirectoryChange> = HashMap::new();
+    for file in compared {
+        let change = changes.get(file);
+        for directory in file.ancestors().skip(1) {
+            directories
+                .entry(directory)
+                .and_modify(|directory_change| {
+                    if let DirectoryChange::Uniform(other_change) = directory_change {
+                        if *other_change != change {
+                            *directory_change = DirectoryChange::Mixed;
+                        }
+                    }
+                })
+                .or_insert(DirectoryChange::Uniform(change));
+        }
+    }
+
+    let mut collapsed: BTreeMap<String, PathChange> = BTreeMap::new();
+    for (file, change) in changes {
+        // Ancestors go from the file up, so the last uniform one is the highest.
+        let directory = file.ancestors().skip(1).filter(|directory| {
+            matches!(
+                directories.get(directory),
+                Some(DirectoryChange::Uniform(Some(_)))
+            )
+        });
+        let path = match directory.last() {
+            Some(directory) if directory.as_os_str().is_empty() => "/".to_string(),
+            Some(directory) => format!("/{}/", directory.to_string_lossy()),
+            None => format!("/{}", file.to_string_lossy()),
+        };
+        collapsed
+            .entry(path.clone())
+            .or_insert_with(|| PathChange {
+                path,
+                files: 0,
+                change: change.clone(),
+            })
+            .files += 1;
+    }
+    collapsed.into_values().collect()
+}
+
+/// Reports the `changes` to each file individually.
+pub fn per_file_changes(changes: &BTreeMap<PathBuf, OwnershipChange>) -> Vec<PathChange> {
+    changes
+        .iter()
+        .map(|(file, change)| PathChange {
+            path: format!("/{}", file.to_string_lossy()),
+            files: 1,
+            change: change.clone(),
+        })
+        .collect()
+}
+
+pub fn to_diff_string(path_changes: &[PathChange]) -> String {
+    if path_changes.is_empty() {
+        return "No ownership changes".to_string();
+    }
+    to_path_lines(path_changes)
+}
+
+/// Renders the changes to files present at both revisions, followed by any added and removed files,
+/// each under its own heading.
+pub fn to_diff_report(
+    changed: &[PathChange],
+    added: &[PathChange],
+    removed: &[PathChange],
+) -> String {
+    let mut sections = Vec::new();
+    if !changed.is_empty() {
+        sections.push(to_path_lines(changed));
+    }
+    if !added.is_empty() {
+        sections.push(format!("Added files:\n{}", to_path_lines(added)));
+    }
+    if !removed.is_empty() {
+        sections.push(format!("Removed files:\n{}", to_path_lines(removed)));
+    }
+    if sections.is_empty() {
+        return "No ownership changes".to_string();
+    }
+    sections.join("\n\n")
+}
+
+fn to_path_lines(path_changes: &[PathChange]) -> String {
+    path_changes
+        .iter()
+        .map(|path_change| {
+            let mut line = path_change.path.clone();
+            if path_change.path.ends_with('/') {
+                let noun = if path_change.files == 1 {
+                    "file"
+                } else {
+                    "files"
+                };
+                line.push_str(&format!(" ({} {})", path_change.files, noun));
+            }
+            line.push(':');
+            for owner in &path_change.change.added {
+                line.push_str(&format!(" +{}", owner));
+            }
+            for owner in &path_change.change.removed {
+                line.push_str(&format!(" -{}", owner));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allow_filter::AllowList;
+    use crate::config::Config;
+    use crate::file_source::GitTree;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+    use crate::ownership_diff::{
+        added_files, collapse_changes, diff_file_owners, file_owners, per_file_changes,
+        removed_files, to_diff_report, to_diff_string, FileOwners, OwnershipChange, PathChange,
+    };
+    use crate::pipeline::{apply_expiry, resolve_codeowners};
+    use crate::test_utils::{commit_all, create_test_file};
+    use git2::Repository;
+    use indoc::indoc;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    const OWNERS_FILE_NAMES: [&str; 1] = ["OWNERS"];
+
+    fn owners(owners: &[&str]) -> BTreeSet<String> {
+        owners.iter().map(|owner| owner.to_string()).collect()
+    }
+
+    /// Resolved as the diff command does, with the default config.
+    fn file_owners_at(root: &Path, revision: &str) -> anyhow::Result<FileOwners> {
+        let tree = GitTree::new(root, revision)?;
+        let allow_list =
+            AllowList::from(tree.files().cloned().collect(), false, &OWNERS_FILE_NAMES)?;
+        let mut owners_tree = OwnersTree::load_from_files(
+            root,
+            &LoadOptions {
+                allow_filter: &allow_list,
+                owners_file_names: &OWNERS_FILE_NAMES,
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &tree,
+                cache: None,
+            },
+        )?;
+        let config = Config::default();
+        apply_expiry(&mut owners_tree, &config);
+        let (codeowners, _) = resolve_codeowners(&owners_tree, &config)?;
+        let files = tree
+            .files()
+            .map(|file| file.strip_prefix(root))
+            .collect::<Result<Vec<_>, _>>()?;
+        file_owners(&codeowners, files)
+    }
+
+    #[test]
+    fn diff_between_revisions() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(
+            &temp_dir,
+            "foo/OWNERS",
+            indoc! {"\
+                include /shared/OWNERS
+                [*.md]
+                mary.jackson
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "shared/OWNERS", "grace.hopper\n")?;
+        create_test_file(&temp_dir, "foo/a.rs", "")?;
+        create_test_file(&temp_dir, "foo/b.rs", "")?;
+        create_test_file(&temp_dir, "foo/README.md", "")?;
+        create_test_file(&temp_dir, "bar/c.rs", "")?;
+        let before = commit_all(&repo, "Before")?;
+
+        create_test_file(&temp_dir, "shared/OWNERS", "margaret.hamilton\n")?;
+        create_test_file(&temp_dir, "bar/OWNERS", "katherine.johnson\n")?;
+        create_test_file(&temp_dir, "bar/d.rs", "")?;
+        let after = commit_all(&repo, "After")?;
+        // Uncommitted changes aren't used
+        create_test_file(&temp_dir, "OWNERS", "alan.turing\n")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let before_owners = file_owners_at(&root, &before)?;
+        let after_owners = file_owners_at(&root, &after)?;
+        assert_eq!(
+            before_owners[Path::new("foo/a.rs")],
+            owners(&["@ada.lovelace", "@grace.hopper"])
+        );
+
+        let changes = diff_file_owners(&before_owners, &after_owners, false);
+        let compared = before_owners
+            .keys()
+            .filter(|file| after_owners.contains_key(*file))
+            .collect::<Vec<_>>();
+        let collapsed = collapse_changes(&changes, compared);
+        assert_eq!(
+            collapsed,
+            vec![
+                PathChange {
+                    path: "/bar/".to_string(),
+                    files: 1,
+                    change: OwnershipChange {
+                        added: owners(&["@katherine.johnson"]),
+                        removed: owners(&[]),
+                    },
+                },
+                PathChange {
+                    path: "/foo/".to_string(),
+                    files: 4,
+                    change: OwnershipChange {
+                        added: owners(&["@margaret.hamilton"]),
+                        removed: owners(&["@grace.hopper"]),
+                    },
+                },
+                PathChange {
+                    path: "/shared/".to_string(),
+                    files: 1,
+                    change: OwnershipChange {
+                        added: owners(&["@margaret.hamilton"]),
+                        removed: owners(&["@grace.hopper"]),
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            to_diff_string(&collapsed),
+            indoc! {"\
+                /bar/ (1 file): +@katherine.johnson
+                /foo/ (4 files): +@margaret.hamilton -@grace.hopper
+                /shared/ (1 file): +@margaret.hamilton -@grace.hopper"
+            }
+        );
+
+        let lost = diff_file_owners(&before_owners, &after_owners, true);
+        assert_eq!(
+            lost.keys().collect::<Vec<_>>(),
+            vec![
+                Path::new("foo/OWNERS"),
+                Path::new("foo/README.md"),
+                Path::new("foo/a.rs"),
+                Path::new("foo/b.rs"),
+                Path::new("shared/OWNERS"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_added_and_removed_files() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "docs/OWNERS", "grace.hopper\n")?;
+        create_test_file(&temp_dir, "docs/guide.md", "")?;
+        create_test_file(&temp_dir, "old/a.rs", "")?;
+        create_test_file(&temp_dir, "old/b.rs", "")?;
+        let before = commit_all(&repo, "Before")?;
+
+        // Moved out of docs/, losing its owners
+        fs::rename(
+            temp_dir.path().join("docs/guide.md"),
+            temp_dir.path().join("guide.md"),
+        )?;
+        fs::remove_dir_all(temp_dir.path().join("old"))?;
+        create_test_file(&temp_dir, "new/c.rs", "")?;
+        let after = commit_all(&repo, "After")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let before_owners = file_owners_at(&root, &before)?;
+        let after_owners = file_owners_at(&root, &after)?;
+
+        // Only files present at both revisions are compared
+        assert!(diff_file_owners(&before_owners, &after_owners, true).is_empty());
+
+        let added = added_files(&before_owners, &after_owners);
+        let removed = removed_files(&before_owners, &after_owners);
+        assert_eq!(
+            removed[Path::new("docs/guide.md")].removed,
+            owners(&["@ada.lovelace", "@grace.hopper"])
+        );
+        assert_eq!(
+            to_diff_report(
+                &[],
+                &collapse_changes(&added, after_owners.keys()),
+                &collapse_changes(&removed, before_owners.keys()),
+            ),
+            indoc! {"\
+                Added files:
+                /guide.md: +@ada.lovelace
+                /new/ (1 file): +@ada.lovelace
+
+                Removed files:
+                /docs/guide.md: -@ada.lovelace -@grace.hopper
+                /old/ (2 files): -@ada.lovelace"
+            }
+        );
+        assert_eq!(to_diff_report(&[], &[], &[]), "No ownership changes");
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_mixed_changes() {
+        let added = OwnershipChange {
+            added: owners(&["@grace.hopper"]),
+            ..OwnershipChange::default()
+        };
+        let removed = OwnershipChange {
+            removed: owners(&["@ada.lovelace"]),
+            ..OwnershipChange::default()
+        };
+        let changes = BTreeMap::from([
+            (PathBuf::from("foo/a.rs"), added.clone()),
+            (PathBuf::from("foo/bar/b.rs"), added.clone()),
+            (PathBuf::from("foo/bar/c.rs"), added),
+            (PathBuf::from("foo/baz/d.rs"), removed.clone()),
+        ]);
+        let compared = changes
+            .keys()
+            .cloned()
+            .chain([PathBuf::from("foo/baz/e.rs")])
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            to_diff_string(&collapse_changes(&changes, &compared)),
+            indoc! {"\
+                /foo/a.rs: +@grace.hopper
+                /foo/bar/ (2 files): +@grace.hopper
+                /foo/baz/d.rs: -@ada.lovelace"
+            }
+        );
+        assert_eq!(per_file_changes(&changes).len(), 4);
+
+        // Every file changing the same way collapses to the root
+        let changes = BTreeMap::from([
+            (PathBuf::from("a.rs"), removed.clone()),
+            (PathBuf::from("foo/b.rs"), removed),
+        ]);
+        assert_eq!(
+            to_diff_string(&collapse_changes(&changes, changes.keys())),
+            "/ (2 files): -@ada.lovelace"
+        );
+        assert_eq!(
+            to_diff_string(&collapse_changes(&BTreeMap::new(), &compared)),
+            "No ownership changes"
+        );
+    }
+}
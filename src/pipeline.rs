@@ -1,12 +1,77 @@
 use crate::allow_filter::AllowFilter;
-use crate::codeowners::{generate_codeowners, to_codeowners_string};
-use crate::owners_tree::OwnersTree;
+use crate::baseline::Baseline;
+use crate::codeowners::{
+    self, generate_codeowners_with_sources, to_codeowners_string,
+    to_codeowners_string_with_comments, to_json_string, RuleComments, RuleSources,
+};
+use crate::compaction;
+use crate::config::Config;
+use crate::expiry;
+use crate::file_source::FileSource;
+use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+use crate::policy::{self, PolicyFile};
+use crate::teams::{TeamMembership, TeamMode};
+use anyhow::anyhow;
 use indoc::indoc;
-use std::fs;
-use std::fs::create_dir_all;
-use std::path::PathBuf;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use textwrap::wrap;
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// GitHub compatible CODEOWNERS file.
+    #[default]
+    Codeowners,
+    /// JSON export of the CODEOWNERS rules.
+    Json,
+}
+
+/// Somewhere to write the generated ownership to.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputTarget {
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// File to write to. Writes to stdout when not specified.
+    pub path: Option<PathBuf>,
+}
+
+impl OutputTarget {
+    pub fn codeowners(path: PathBuf) -> OutputTarget {
+        OutputTarget {
+            format: OutputFormat::Codeowners,
+            path: Some(path),
+        }
+    }
+}
+
+impl FromStr for OutputTarget {
+    type Err = anyhow::Error;
+
+    /// Parses an output target in the format `<format>[:<path>]`, e.g., `json:owners.json`.
+    fn from_str(text: &str) -> anyhow::Result<OutputTarget> {
+        let (format, path) = match text.split_once(':') {
+            Some((format, path)) => (format, Some(PathBuf::from(path))),
+            None => (text, None),
+        };
+        let format = match format {
+            "codeowners" => OutputFormat::Codeowners,
+            "json" => OutputFormat::Json,
+            _ => {
+                return Err(anyhow!(
+                    "Invalid output format '{}'. Expected 'codeowners' or 'json'.",
+                    format
+                ))
+            }
+        };
+        Ok(OutputTarget { format, path })
+    }
+}
+
 fn get_auto_generated_notice<S: AsRef<str>>(message: Option<S>) -> String {
     let mut out = indoc! {"\
         ################################################################################
@@ -32,54 +97,375 @@ fn get_auto_generated_notice<S: AsRef<str>>(message: Option<S>) -> String {
     out
 }
 
-pub fn generate_codeowners_from_files<F, S>(
+/// Generates ownership from the OWNERS files under `repo_root`, as set in the `config`, writing it
+/// to each of its output targets in `destination`. The tree is only loaded and resolved once,
+/// regardless of the number of targets. With `write_baseline`, policy violations are written to the
+/// configured baseline rather than failing generation.
+pub fn generate_codeowners_from_files<F, N>(
     repo_root: Option<PathBuf>,
-    output_file: Option<PathBuf>,
-    implicit_inherit: bool,
-    allow_filter: &F,
-    message: Option<S>,
+    config: &Config,
+    destination: &dyn FileSource,
+    load_options: &LoadOptions<F, N>,
+    write_baseline: bool,
 ) -> anyhow::Result<()>
 where
     F: AllowFilter,
-    S: AsRef<str>,
+    N: AsRef<str> + Sync,
 {
     let root = repo_root.unwrap_or(std::env::current_dir()?);
-    let tree = OwnersTree::load_from_files(root, allow_filter)?;
+    let (codeowners, comments) =
+        load_checked_codeowners(root, config, load_options, write_baseline)?;
+    check_codeowners_size(&codeowners, comments.as_ref(), config)?;
+    write_outputs(
+        &codeowners,
+        comments.as_ref(),
+        &config.output_targets(),
+        destination,
+        config.message.as_ref(),
+    )
+}
 
-    let codeowners = generate_codeowners(&tree, implicit_inherit)?;
-    let mut codeowners_text = to_codeowners_string(codeowners);
-    let auto_generated_notice = get_auto_generated_notice(message);
+/// CODEOWNERS rules, along with the comments to write before them, if enabled.
+pub type ResolvedCodeowners = (HashMap<String, HashSet<String>>, Option<RuleComments>);
+
+/// Loads the OWNERS files under `root`, drops expired owners, checks them against the configured
+/// policies, and resolves them into CODEOWNERS rules, compacted if configured. With
+/// `write_baseline`, policy violations are written to the configured baseline instead of failing.
+pub fn load_checked_codeowners<P, F, N>(
+    root: P,
+    config: &Config,
+    load_options: &LoadOptions<F, N>,
+    write_baseline: bool,
+) -> anyhow::Result<ResolvedCodeowners>
+where
+    P: AsRef<Path>,
+    F: AllowFilter,
+    N: AsRef<str> + Sync,
+{
+    let mut tree = OwnersTree::load_from_files(root, load_options)?;
+    apply_expiry(&mut tree, config);
+    check_policies(&tree, config, write_baseline)?;
+    let (mut codeowners, comments) = resolve_codeowners(&tree, config)?;
+    if config.compact() {
+        let recurse_submodules = load_options.submodule_mode == SubmoduleMode::Recurse;
+        let files = load_options
+            .source
+            .tracked_files(&tree.path, recurse_submodules)
+            .map_err(|error| anyhow!("Compaction needs the tracked files: {}", error))?;
+        compact_codeowners(&mut codeowners, &tree.path, files.iter())?;
+    }
+    Ok((codeowners, comments))
+}
 
-    codeowners_text =
-        format!("{auto_generated_notice}\n\n{codeowners_text}\n\n{auto_generated_notice}");
+/// Resolves the tree of OWNERS files into CODEOWNERS rules, including any rules added, or owners
+/// adjusted, per the config.
+pub fn resolve_codeowners(
+    tree: &OwnersTree,
+    config: &Config,
+) -> anyhow::Result<ResolvedCodeowners> {
+    let (mut codeowners, mut sources) =
+        generate_codeowners_with_sources(tree, config.implicit_inherit())?;
+    if config.protect_owners_files() {
+        protect_owners_files(tree, &mut codeowners, &mut sources, config)?;
+    }
+    if let Some(teams_file) = &config.teams_file {
+        adjust_team_owners(&mut codeowners, teams_file, config.team_mode())?;
+    }
+    let comments = config
+        .source_comments()
+        .then(|| codeowners::source_comments(tree, &sources));
+    Ok((codeowners, comments))
+}
 
-    match output_file {
-        None => println!("{}", codeowners_text),
-        Some(output_file) => {
-            if let Some(parent_dir) = output_file.parent() {
-                create_dir_all(parent_dir)?;
+/// Drops expired owners from the tree, warning about those expiring soon.
+pub fn apply_expiry(tree: &mut OwnersTree, config: &Config) {
+    let today = config.today();
+    for owner in expiry::owners_expiring_within(tree, today, config.expiry_warning_days()) {
+        warn!("Temporary owner {}", owner);
+    }
+    for owner in expiry::drop_expired_owners(tree, today) {
+        info!("Dropped expired owner {}", owner);
+    }
+}
+
+/// Adds rules giving each OWNERS file to its directory's owners, and leaves the CODEOWNERS files
+/// written per the `config` unowned, so changes to ownership need approval from the owners
+/// affected.
+pub fn protect_owners_files(
+    tree: &OwnersTree,
+    codeowners: &mut HashMap<String, HashSet<String>>,
+    sources: &mut RuleSources,
+    config: &Config,
+) -> anyhow::Result<()> {
+    codeowners::add_owners_file_rules(tree, codeowners, sources)?;
+    codeowners::add_unowned_rules(generated_codeowners_files(&tree.path, config)?, codeowners);
+    Ok(())
+}
+
+/// The CODEOWNERS files written within the canonical `repo_root`, relative to it.
+fn generated_codeowners_files(repo_root: &Path, config: &Config) -> anyhow::Result<Vec<PathBuf>> {
+    let current_dir = std::env::current_dir()?;
+    let mut files = Vec::new();
+    for output_target in config.output_targets() {
+        if output_target.format != OutputFormat::Codeowners {
+            continue;
+        }
+        let Some(path) = output_target.path else {
+            continue;
+        };
+        let path = current_dir.join(path);
+        // The file, and its directory, may not have been written yet, so the closest existing
+        // ancestor is canonicalized instead.
+        let existing_ancestor = path.ancestors().find_map(|ancestor| {
+            let canonical_ancestor = ancestor.canonicalize().ok()?;
+            Some((ancestor, canonical_ancestor))
+        });
+        let path = match existing_ancestor {
+            Some((ancestor, canonical_ancestor)) => {
+                canonical_ancestor.join(path.strip_prefix(ancestor)?)
             }
+            None => path,
+        };
+        if let Ok(relative_path) = path.strip_prefix(repo_root) {
+            files.push(relative_path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
 
-            // Files should end with a newline
-            codeowners_text.push('\n');
+/// Adjusts owners using the team membership in `teams_file`, reporting each change.
+pub fn adjust_team_owners(
+    codeowners: &mut HashMap<String, HashSet<String>>,
+    teams_file: &Path,
+    team_mode: TeamMode,
+) -> anyhow::Result<()> {
+    let membership = TeamMembership::from_file(teams_file)?;
+    let (changes, action) = match team_mode {
+        TeamMode::Collapse => (
+            membership.collapse_team_members(codeowners),
+            "Collapsed team members",
+        ),
+        TeamMode::Expand => (membership.expand_teams(codeowners)?, "Expanded teams"),
+    };
+    for change in &changes {
+        match team_mode {
+            TeamMode::Collapse => info!(
+                "{}: dropped {}, covered by {}",
+                change.pattern,
+                change.members.join(", "),
+                change.team
+            ),
+            TeamMode::Expand => info!(
+                "{}: replaced {} with {}",
+                change.pattern,
+                change.team,
+                change.members.join(", ")
+            ),
+        }
+    }
+    let rules = changes
+        .iter()
+        .map(|change| &change.pattern)
+        .collect::<HashSet<_>>();
+    info!("{} in {} rules", action, rules.len());
+    Ok(())
+}
+
+/// Removes rules which don't change the effective ownership of any file, checking that the owners
+/// of each of the tracked `files`, under the canonical `repo_root`, are unchanged.
+pub fn compact_codeowners<'a, I>(
+    codeowners: &mut HashMap<String, HashSet<String>>,
+    repo_root: &Path,
+    files: I,
+) -> anyhow::Result<()>
+where
+    I: Iterator<Item = &'a PathBuf>,
+{
+    let before = codeowners.clone();
+    let report = compaction::compact_codeowners(codeowners);
+    let files = files.filter_map(|file| file.strip_prefix(repo_root).ok());
+    compaction::verify_same_ownership(&before, codeowners, files)?;
+    info!(
+        "Compaction removed {} redundant rules and merged {} sibling overrides, leaving {} of {}",
+        report.redundant.len(),
+        report.merged.len(),
+        report.rules_after(),
+        report.rules_before
+    );
+    Ok(())
+}
+
+/// Reports the size of the generated CODEOWNERS file, failing or warning if it exceeds the
+/// configured limit.
+pub fn check_codeowners_size(
+    codeowners: &HashMap<String, HashSet<String>>,
+    comments: Option<&RuleComments>,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let writes_codeowners = config
+        .output_targets()
+        .iter()
+        .any(|output_target| output_target.format == OutputFormat::Codeowners);
+    if !writes_codeowners {
+        return Ok(());
+    }
+    let size = to_codeowners_file(codeowners, comments, config.message.as_ref()).len();
+    info!("Generated CODEOWNERS is {} bytes", size);
+    if size as u64 <= config.size_limit() {
+        return Ok(());
+    }
+    let mut message = format!(
+        "Generated CODEOWNERS is {} bytes, over the limit of {} bytes. GitHub ignores CODEOWNERS \
+        files of 3 MB or more.",
+        size,
+        config.size_limit()
+    );
+    if !config.compact() {
+        message.push_str(" Try --compact to reduce its size.");
+    }
+    if config.fail_on_size_limit() {
+        return Err(anyhow!(message));
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+/// Fails if the OWNERS files don't meet the configured policies, other than for violations in the
+/// baseline. With `write_baseline`, all current violations are written to the baseline instead.
+pub fn check_policies(
+    tree: &OwnersTree,
+    config: &Config,
+    write_baseline: bool,
+) -> anyhow::Result<()> {
+    let implicit_inherit = config.implicit_inherit();
+    let mut violations = Vec::new();
+    if let Some(min_owners) = &config.min_owners {
+        violations.extend(
+            policy::check_min_owners(tree, implicit_inherit, min_owners)?
+                .into_iter()
+                .map(|violation| {
+                    violation.to_policy_violation(policy::MIN_OWNERS_RULE_ID, min_owners)
+                }),
+        );
+    }
+    if let Some(policy_file) = &config.policy_file {
+        let policy_file = PolicyFile::from_file(policy_file)?;
+        violations.extend(policy::check_policy_file(
+            tree,
+            implicit_inherit,
+            &policy_file,
+        )?);
+    }
 
-            fs::write(output_file, codeowners_text)?;
+    if let Some(baseline_path) = &config.baseline {
+        if write_baseline {
+            let baseline = Baseline::from_violations(&violations);
+            baseline.save(baseline_path)?;
+            info!(
+                "Wrote {} violations to the baseline {:?}",
+                baseline.violation_count(),
+                baseline_path
+            );
+            return Ok(());
         }
+        violations = Baseline::load(baseline_path)?.new_violations(violations);
+    }
+    if !violations.is_empty() {
+        return Err(anyhow!(
+            "{}",
+            policy::to_policy_violations_string(&violations)
+        ));
     }
+    Ok(())
+}
+
+/// Renders the full CODEOWNERS file, as written to CODEOWNERS outputs.
+pub fn to_codeowners_file<S: AsRef<str>>(
+    codeowners: &HashMap<String, HashSet<String>>,
+    comments: Option<&RuleComments>,
+    message: Option<S>,
+) -> String {
+    let codeowners_text = match comments {
+        Some(comments) => to_codeowners_string_with_comments(codeowners.clone(), comments),
+        None => to_codeowners_string(codeowners.clone()),
+    };
+    let auto_generated_notice = get_auto_generated_notice(message);
+    format!("{auto_generated_notice}\n\n{codeowners_text}\n\n{auto_generated_notice}")
+}
+
+/// Renders the CODEOWNERS rules to each of the `output_targets`, with files written to
+/// `destination`. Any `comments` are written before the rules in CODEOWNERS outputs.
+pub fn write_outputs<S: AsRef<str>>(
+    codeowners: &HashMap<String, HashSet<String>>,
+    comments: Option<&RuleComments>,
+    output_targets: &[OutputTarget],
+    destination: &dyn FileSource,
+    message: Option<S>,
+) -> anyhow::Result<()> {
+    for output_target in output_targets {
+        let text = match output_target.format {
+            OutputFormat::Codeowners => to_codeowners_file(codeowners, comments, message.as_ref()),
+            OutputFormat::Json => to_json_string(codeowners.clone())?,
+        };
+        write_output(output_target.path.as_deref(), destination, text)?;
+    }
+
+    Ok(())
+}
 
+fn write_output(
+    output_file: Option<&Path>,
+    destination: &dyn FileSource,
+    mut text: String,
+) -> anyhow::Result<()> {
+    match output_file {
+        None => println!("{}", text),
+        Some(output_file) => {
+            // Files should end with a newline
+            text.push('\n');
+
+            // Leave unchanged files alone, so their modification times are preserved.
+            if destination
+                .read_to_string(output_file)
+                .map_or(false, |existing| existing == text)
+            {
+                debug!("{:?} is unchanged", output_file);
+                return Ok(());
+            }
+            destination.write(output_file, &text)?;
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use crate::allow_filter::FilterGitMetadata;
-    use crate::pipeline::{generate_codeowners_from_files, get_auto_generated_notice};
-    use crate::test_utils::create_test_file;
+    use crate::allow_filter::{AllowList, FilterGitMetadata};
+    use crate::cache::ParseCache;
+    use crate::config::Config;
+    use crate::file_source::{FileSource, GitTree, InMemoryFiles, WorkingTree};
+    use crate::owners_tree::{LoadOptions, SubmoduleMode};
+    use crate::pipeline::{
+        generate_codeowners_from_files, get_auto_generated_notice, OutputFormat, OutputTarget,
+    };
+    use crate::policy::MinOwnersPolicy;
+    use crate::teams::TeamMode;
+    use crate::test_utils::{commit_all, create_test_file};
+    use git2::Repository;
     use indoc::indoc;
     use std::fs;
+    use std::path::{Path, PathBuf};
     use tempfile::tempdir;
 
     const ALLOW_ANY: FilterGitMetadata = FilterGitMetadata {};
+    const OWNERS_FILE_NAMES: [&str; 1] = ["OWNERS"];
+    const LOAD_OPTIONS: LoadOptions<FilterGitMetadata, &str> = LoadOptions {
+        allow_filter: &ALLOW_ANY,
+        owners_file_names: &OWNERS_FILE_NAMES,
+        submodule_mode: SubmoduleMode::Ignore,
+        source: &WorkingTree {},
+        cache: None,
+    };
 
     #[test]
     fn test_generate_codeowners_from_files_simple() -> anyhow::Result<()> {
@@ -119,16 +505,12 @@ mod test {
 
         let output_file = root_dir.join("CODEOWNERS");
         let repo_root = Some(root_dir.to_path_buf());
-        let implicit_inherit = true;
-        let message = Option::<String>::None;
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
 
-        generate_codeowners_from_files(
-            repo_root,
-            Some(output_file.clone()),
-            implicit_inherit,
-            &ALLOW_ANY,
-            message,
-        )?;
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
 
         let generated_codeowners = fs::read_to_string(output_file)?;
 
@@ -182,16 +564,12 @@ mod test {
 
         let output_file = root_dir.join("CODEOWNERS");
         let repo_root = Some(root_dir.to_path_buf());
-        let implicit_inherit = true;
-        let message = Option::<String>::None;
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
 
-        generate_codeowners_from_files(
-            repo_root,
-            Some(output_file.clone()),
-            implicit_inherit,
-            &ALLOW_ANY,
-            message,
-        )?;
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
 
         let generated_codeowners = fs::read_to_string(output_file)?;
 
@@ -250,16 +628,12 @@ mod test {
 
         let output_file = root_dir.join("CODEOWNERS");
         let repo_root = Some(root_dir.to_path_buf());
-        let implicit_inherit = true;
-        let message = Option::<String>::None;
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
 
-        generate_codeowners_from_files(
-            repo_root,
-            Some(output_file.clone()),
-            implicit_inherit,
-            &ALLOW_ANY,
-            message,
-        )?;
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
 
         let generated_codeowners = fs::read_to_string(output_file)?;
 
@@ -319,16 +693,12 @@ mod test {
 
         let output_file = root_dir.join("CODEOWNERS");
         let repo_root = Some(root_dir.to_path_buf());
-        let implicit_inherit = true;
-        let message = Option::<String>::None;
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
 
-        generate_codeowners_from_files(
-            repo_root,
-            Some(output_file.clone()),
-            implicit_inherit,
-            &ALLOW_ANY,
-            message,
-        )?;
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
 
         let generated_codeowners = fs::read_to_string(output_file)?;
 
@@ -406,16 +776,12 @@ mod test {
 
         let output_file = root_dir.join("CODEOWNERS");
         let repo_root = Some(root_dir.to_path_buf());
-        let implicit_inherit = true;
-        let message = Option::<String>::None;
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
 
-        generate_codeowners_from_files(
-            repo_root,
-            Some(output_file.clone()),
-            implicit_inherit,
-            &ALLOW_ANY,
-            message,
-        )?;
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
 
         let generated_codeowners = fs::read_to_string(output_file)?;
 
@@ -424,6 +790,523 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_generate_codeowners_from_files_multiple_outputs() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root_dir = temp_dir.path();
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {
+                "ada.lovelace
+                [*.rs]
+                grace.hopper
+                "
+            },
+        )?;
+
+        let expected_codeowners = indoc! {"\
+            ################################################################################
+            #                             AUTO GENERATED FILE
+            #                            Do Not Manually Update
+            #                              For details, see:
+            #        https://github.com/andrewring/github-distributed-owners#readme
+            ################################################################################
+
+            * @ada.lovelace
+            /*.rs @ada.lovelace @grace.hopper
+
+            ################################################################################
+            #                             AUTO GENERATED FILE
+            #                            Do Not Manually Update
+            #                              For details, see:
+            #        https://github.com/andrewring/github-distributed-owners#readme
+            ################################################################################
+            "
+        };
+        let expected_json = indoc! {r#"
+            {
+              "rules": [
+                {
+                  "pattern": "*",
+                  "owners": [
+                    "@ada.lovelace"
+                  ]
+                },
+                {
+                  "pattern": "/*.rs",
+                  "owners": [
+                    "@ada.lovelace",
+                    "@grace.hopper"
+                  ]
+                }
+              ]
+            }
+            "#
+        };
+
+        let output_targets = [
+            OutputTarget::codeowners(root_dir.join(".github/CODEOWNERS")),
+            OutputTarget::codeowners(root_dir.join("docs/CODEOWNERS")),
+            OutputTarget {
+                format: OutputFormat::Json,
+                path: Some(root_dir.join("owners.json")),
+            },
+        ];
+        let repo_root = Some(root_dir.to_path_buf());
+        let config = Config {
+            outputs: Some(output_targets.to_vec()),
+            ..Config::default()
+        };
+
+        generate_codeowners_from_files(repo_root, &config, &WorkingTree {}, &LOAD_OPTIONS, false)?;
+
+        assert_eq!(
+            fs::read_to_string(root_dir.join(".github/CODEOWNERS"))?,
+            expected_codeowners
+        );
+        assert_eq!(
+            fs::read_to_string(root_dir.join("docs/CODEOWNERS"))?,
+            expected_codeowners
+        );
+        assert_eq!(
+            fs::read_to_string(root_dir.join("owners.json"))?,
+            expected_json.trim_start()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_with_cache() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root_dir = temp_dir.path();
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "foo/OWNERS", "include /shared/OWNERS\n")?;
+        create_test_file(&temp_dir, "shared/OWNERS", "grace.hopper\n")?;
+        let output_file = root_dir.join("CODEOWNERS");
+        let cache_file = root_dir.join("cache.json");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
+
+        let generate = || -> anyhow::Result<String> {
+            let cache = ParseCache::load(&cache_file);
+            generate_codeowners_from_files(
+                Some(root_dir.to_path_buf()),
+                &config,
+                &WorkingTree {},
+                &LoadOptions {
+                    cache: Some(&cache),
+                    ..LOAD_OPTIONS
+                },
+                false,
+            )?;
+            cache.save()?;
+            Ok(fs::read_to_string(&output_file)?)
+        };
+
+        let generated_codeowners = generate()?;
+        let modified = fs::metadata(&output_file)?.modified()?;
+        assert!(generated_codeowners.contains("/foo/ @ada.lovelace @grace.hopper\n"));
+
+        // Unchanged output isn't rewritten
+        assert_eq!(generate()?, generated_codeowners);
+        assert_eq!(fs::metadata(&output_file)?.modified()?, modified);
+
+        // Changes to included files are picked up
+        create_test_file(&temp_dir, "shared/OWNERS", "margaret.hamilton\n")?;
+        assert!(generate()?.contains("/foo/ @ada.lovelace @margaret.hamilton\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_at_revision() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let repo = Repository::init(temp_dir.path())?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(&temp_dir, "foo/OWNERS", "include /shared/OWNERS\n")?;
+        create_test_file(&temp_dir, "shared/OWNERS", "grace.hopper\n")?;
+        let revision = commit_all(&repo, "First")?;
+        // Neither working tree changes nor untracked files are used
+        create_test_file(&temp_dir, "shared/OWNERS", "margaret.hamilton\n")?;
+        create_test_file(&temp_dir, "bar/OWNERS", "katherine.johnson\n")?;
+
+        let root_dir = temp_dir.path().canonicalize()?;
+        let tree = GitTree::new(&root_dir, &revision)?;
+        let allow_list =
+            AllowList::from(tree.files().cloned().collect(), false, &OWNERS_FILE_NAMES)?;
+        let output_file = root_dir.join("CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
+        generate_codeowners_from_files(
+            Some(root_dir),
+            &config,
+            &WorkingTree {},
+            &LoadOptions {
+                allow_filter: &allow_list,
+                owners_file_names: &OWNERS_FILE_NAMES,
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &tree,
+                cache: None,
+            },
+            false,
+        )?;
+
+        let generated_codeowners = fs::read_to_string(&output_file)?;
+        assert!(generated_codeowners.contains("/foo/ @ada.lovelace @grace.hopper\n"));
+        assert!(!generated_codeowners.contains("margaret.hamilton"));
+        assert!(!generated_codeowners.contains("katherine.johnson"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_in_memory() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/OWNERS", "grace.hopper\n"),
+        ]);
+        let output_file = PathBuf::from("/repo/.github/CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
+        generate_codeowners_from_files(
+            Some(PathBuf::from("/repo")),
+            &config,
+            &files,
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+            false,
+        )?;
+
+        let generated_codeowners = files.read_to_string(&output_file)?;
+        assert!(
+            generated_codeowners.contains("\n* @ada.lovelace\n/foo/ @ada.lovelace @grace.hopper\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_with_policies() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root_dir = temp_dir.path();
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {"\
+                ada.lovelace
+                grace.hopper
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "foo/OWNERS",
+            indoc! {"\
+                set inherit = false
+                margaret.hamilton
+                "
+            },
+        )?;
+        let output_file = root_dir.join("CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            min_owners: Some(MinOwnersPolicy {
+                individuals: Some(2),
+                ..MinOwnersPolicy::default()
+            }),
+            ..Config::default()
+        };
+        let generate = |config: &Config, write_baseline: bool| {
+            generate_codeowners_from_files(
+                Some(root_dir.to_path_buf()),
+                config,
+                &WorkingTree {},
+                &LOAD_OPTIONS,
+                write_baseline,
+            )
+        };
+
+        let error = generate(&config, false).unwrap_err().to_string();
+        assert!(error.contains("[min-owners] /foo/OWNERS: /foo/ has 1 individual"));
+        assert!(!output_file.exists());
+
+        // Known violations are written to the baseline, and no longer fail generation
+        let config = Config {
+            baseline: Some(root_dir.join("baseline.json")),
+            ..config
+        };
+        generate(&config, true)?;
+        generate(&config, false)?;
+        assert!(fs::read_to_string(&output_file)?.contains("/foo/ @margaret.hamilton\n"));
+
+        // New violations still do
+        create_test_file(
+            &temp_dir,
+            "bar/OWNERS",
+            indoc! {"\
+                set inherit = false
+                mary.jackson
+                "
+            },
+        )?;
+        let error = generate(&config, false).unwrap_err().to_string();
+        assert!(error.contains("/bar/OWNERS"));
+        assert!(!error.contains("/foo/OWNERS"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_with_expired_owners() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root_dir = temp_dir.path();
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {"\
+                ada.lovelace
+                grace.hopper expires=2026-06-30
+                katherine.johnson expires=2026-07-01
+                "
+            },
+        )?;
+        let output_file = root_dir.join("CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            now: Some("2026-07-01".parse()?),
+            ..Config::default()
+        };
+
+        generate_codeowners_from_files(
+            Some(root_dir.to_path_buf()),
+            &config,
+            &WorkingTree {},
+            &LOAD_OPTIONS,
+            false,
+        )?;
+
+        assert!(fs::read_to_string(output_file)?.contains("\n* @ada.lovelace @katherine.johnson\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_with_teams() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root_dir = temp_dir.path();
+        create_test_file(
+            &temp_dir,
+            "OWNERS",
+            indoc! {"\
+                @org/platform
+                ada.lovelace
+                alan.turing
+                "
+            },
+        )?;
+        create_test_file(
+            &temp_dir,
+            "teams.toml",
+            indoc! {r#"
+                [teams]
+                "org/platform" = ["ada.lovelace", "grace.hopper"]
+                "#
+            },
+        )?;
+        let output_file = root_dir.join("CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            teams_file: Some(root_dir.join("teams.toml")),
+            ..Config::default()
+        };
+        let generate = |config: &Config| -> anyhow::Result<String> {
+            generate_codeowners_from_files(
+                Some(root_dir.to_path_buf()),
+                config,
+                &WorkingTree {},
+                &LOAD_OPTIONS,
+                false,
+            )?;
+            Ok(fs::read_to_string(&output_file)?)
+        };
+
+        assert!(generate(&config)?.contains("\n* @org/platform @alan.turing\n"));
+
+        let config = Config {
+            team_mode: Some(TeamMode::Expand),
+            ..config
+        };
+        assert!(generate(&config)?.contains("\n* @ada.lovelace @alan.turing @grace.hopper\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_protecting_owners_files() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/OWNERS", "set inherit = false\ngrace.hopper\n"),
+        ]);
+        let output_file = PathBuf::from("/repo/.github/CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            protect_owners_files: Some(true),
+            ..Config::default()
+        };
+        generate_codeowners_from_files(
+            Some(PathBuf::from("/repo")),
+            &config,
+            &files,
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+            false,
+        )?;
+
+        let generated_codeowners = files.read_to_string(&output_file)?;
+        assert!(generated_codeowners.contains(indoc! {"
+            * @ada.lovelace
+            /.github/CODEOWNERS
+            /OWNERS @ada.lovelace
+            /foo/ @grace.hopper
+            /foo/OWNERS @grace.hopper
+            "
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_with_source_comments() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/OWNERS", "include /shared/OWNERS\n"),
+            ("/repo/shared/OWNERS", "grace.hopper\n"),
+        ]);
+        let output_file = PathBuf::from("/repo/.github/CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            source_comments: Some(true),
+            ..Config::default()
+        };
+        generate_codeowners_from_files(
+            Some(PathBuf::from("/repo")),
+            &config,
+            &files,
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+            false,
+        )?;
+
+        let generated_codeowners = files.read_to_string(&output_file)?;
+        assert!(generated_codeowners.contains(indoc! {"
+            # /OWNERS
+            * @ada.lovelace
+
+            # /foo/OWNERS
+            #   includes /shared/OWNERS
+            /foo/ @ada.lovelace @grace.hopper
+            "
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_compacted() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            ("/repo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/OWNERS", "ada.lovelace\n"),
+            ("/repo/foo/main.rs", ""),
+            ("/repo/bar/OWNERS", "grace.hopper\n"),
+        ]);
+        let output_file = PathBuf::from("/repo/.github/CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            compact: Some(true),
+            ..Config::default()
+        };
+        generate_codeowners_from_files(
+            Some(PathBuf::from("/repo")),
+            &config,
+            &files,
+            &LoadOptions {
+                source: &files,
+                ..LOAD_OPTIONS
+            },
+            false,
+        )?;
+
+        // The rule for /foo/ changes no file's owners
+        let generated_codeowners = files.read_to_string(&output_file)?;
+        assert!(generated_codeowners
+            .contains("\n* @ada.lovelace\n/bar/ @ada.lovelace @grace.hopper\n\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_codeowners_from_files_over_size_limit() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([("/repo/OWNERS", "ada.lovelace\n")]);
+        let output_file = PathBuf::from("/repo/.github/CODEOWNERS");
+        let config = Config {
+            output_file: Some(output_file.clone()),
+            size_limit: Some(100),
+            ..Config::default()
+        };
+        let generate = |config: &Config| {
+            generate_codeowners_from_files(
+                Some(PathBuf::from("/repo")),
+                config,
+                &files,
+                &LoadOptions {
+                    source: &files,
+                    ..LOAD_OPTIONS
+                },
+                false,
+            )
+        };
+
+        // Only a warning by default
+        generate(&config)?;
+        assert!(files.is_file(&output_file));
+
+        let config = Config {
+            output_file: Some(PathBuf::from("/repo/docs/CODEOWNERS")),
+            fail_on_size_limit: Some(true),
+            ..config
+        };
+        let error = generate(&config).unwrap_err().to_string();
+        assert!(error.contains("over the limit of 100 bytes"));
+        assert!(error.contains("Try --compact"));
+        assert!(!files.exists(Path::new("/repo/docs/CODEOWNERS")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_output_target() -> anyhow::Result<()> {
+        assert_eq!(
+            "json:owners.json".parse::<OutputTarget>()?,
+            OutputTarget {
+                format: OutputFormat::Json,
+                path: Some(PathBuf::from("owners.json")),
+            }
+        );
+        assert_eq!(
+            "codeowners".parse::<OutputTarget>()?,
+            OutputTarget {
+                format: OutputFormat::Codeowners,
+                path: None,
+            }
+        );
+        assert!("yaml:owners.yaml".parse::<OutputTarget>().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_get_auto_generated_notice_default() {
         let expected = indoc! {"\
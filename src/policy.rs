@@ -0,0 +1,633 @@
+use crate::codeowners::{
+    generate_codeowners_with_sources, is_email, is_team, to_codeowners_owner, to_codeowners_rules,
+};
+use crate::owners_tree::{OwnersTree, TreeNode};
+use anyhow::anyhow;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Rule ID for violations of the `min-owners` section of the config file.
+pub const MIN_OWNERS_RULE_ID: &str = "min-owners";
+
+/// Minimum number of owners for every CODEOWNERS rule. Having enough of either kind of owner is
+/// sufficient, e.g., two individuals or one team.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MinOwnersPolicy {
+    pub individuals: Option<usize>,
+    pub teams: Option<usize>,
+    /// Rule patterns exempt from the policy, as written in CODEOWNERS. Directories, ending in `/`,
+    /// exempt every rule within them.
+    pub exempt: Vec<String>,
+}
+
+impl MinOwnersPolicy {
+    fn is_satisfied(&self, individuals: usize, teams: usize) -> bool {
+        let enough_individuals = self.individuals.map(|minimum| individuals >= minimum);
+        let enough_teams = self.teams.map(|minimum| teams >= minimum);
+        match (enough_individuals, enough_teams) {
+            (None, None) => true,
+            (enough_individuals, enough_teams) => {
+                enough_individuals.unwrap_or(false) || enough_teams.unwrap_or(false)
+            }
+        }
+    }
+
+    fn is_exempt(&self, pattern: &str) -> bool {
+        self.exempt.iter().any(|exempt| {
+            pattern == exempt || (exempt.ends_with('/') && pattern.starts_with(exempt.as_str()))
+        })
+    }
+
+    fn describe(&self) -> String {
+        let mut minimums = Vec::new();
+        if let Some(individuals) = self.individuals {
+            minimums.push(count(individuals, "individual", "individuals"));
+        }
+        if let Some(teams) = self.teams {
+            minimums.push(count(teams, "team", "teams"));
+        }
+        minimums.join(" or ")
+    }
+}
+
+/// A rule with fewer owners than the [MinOwnersPolicy] requires.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct MinOwnersViolation {
+    pub pattern: String,
+    /// The OWNERS file the rule comes from, relative to the repo root.
+    pub owners_file: Option<String>,
+    pub individuals: usize,
+    pub teams: usize,
+}
+
+impl MinOwnersViolation {
+    /// The violation as reported for the rule with `rule_id`.
+    pub fn to_policy_violation(self, rule_id: &str, policy: &MinOwnersPolicy) -> PolicyViolation {
+        PolicyViolation {
+            rule_id: rule_id.to_string(),
+            owners_file: self.owners_file,
+            subject: self.pattern.clone(),
+            message: format!(
+                "{} has {} and {}, fewer than {}",
+                self.pattern,
+                count(self.individuals, "individual", "individuals"),
+                count(self.teams, "team", "teams"),
+                policy.describe()
+            ),
+        }
+    }
+}
+
+/// Renders `path` relative to the repo `root`, with a leading `/`.
+fn relative_path_string(path: &Path, root: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(relative_path) => format!("/{}", relative_path.to_string_lossy()),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Renders the `directory` relative to the repo `root`, as a CODEOWNERS directory pattern.
+fn directory_string(directory: &Path, root: &Path) -> String {
+    let path = relative_path_string(directory, root);
+    if path.ends_with('/') {
+        path
+    } else {
+        format!("{}/", path)
+    }
+}
+
+fn count(number: usize, singular: &str, plural: &str) -> String {
+    if number == 1 {
+        format!("{} {}", number, singular)
+    } else {
+        format!("{} {}", number, plural)
+    }
+}
+
+/// Checks the effective owners of every rule generated from the `owners_tree` against the
+/// `policy`, returning the rules which fall short, in CODEOWNERS order.
+pub fn check_min_owners(
+    owners_tree: &OwnersTree,
+    implicit_inherit: bool,
+    policy: &MinOwnersPolicy,
+) -> anyhow::Result<Vec<MinOwnersViolation>> {
+    let (codeowners, sources) = generate_codeowners_with_sources(owners_tree, implicit_inherit)?;
+    // Only the rules actually written are checked, with owners as written.
+    let violations = to_codeowners_rules(&codeowners)
+        .into_iter()
+        .filter(|rule| !policy.is_exempt(&rule.pattern))
+        .filter_map(|rule| {
+            let teams = rule.owners.iter().filter(|owner| is_team(owner)).count();
+            let individuals = rule.owners.len() - teams;
+            if policy.is_satisfied(individuals, teams) {
+                return None;
+            }
+            let key = if rule.pattern == "*" {
+                "/"
+            } else {
+                &rule.pattern
+            };
+            let owners_file = sources
+                .get(key)
+                .cloned()
+                .flatten()
+                .map(|owners_file| relative_path_string(&owners_file, &owners_tree.path));
+            Some(MinOwnersViolation {
+                pattern: rule.pattern,
+                owners_file,
+                individuals,
+                teams,
+            })
+        })
+        .collect();
+    // Rules are already sorted by pattern.
+    Ok(violations)
+}
+
+/// Declarative rules for the resolved ownership, read from a policy file.
+#[derive(Deserialize, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PolicyFile {
+    pub rules: Vec<PolicyRule>,
+    /// Existing violations which are tolerated, so that rules can be introduced before every
+    /// OWNERS file meets them.
+    pub allowed: Vec<AllowedViolation>,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+pub struct PolicyRule {
+    /// Identifies the rule in violations and the allowlist.
+    pub id: String,
+    #[serde(flatten)]
+    pub check: PolicyCheck,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum PolicyCheck {
+    /// Every rule for paths within the `path` directory must include all of the `owners`.
+    RequiredOwners {
+        path: String,
+        owners: Vec<String>,
+    },
+    /// No owners may be email addresses.
+    NoEmails,
+    /// `set inherit = false` may only be used in OWNERS files within the `directories`.
+    InheritFalseOnlyIn {
+        directories: Vec<String>,
+    },
+    MinOwners(MinOwnersPolicy),
+}
+
+/// A violation to ignore, identified by the rule and the OWNERS file it's reported for.
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AllowedViolation {
+    pub rule: String,
+    pub owners_file: String,
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+pub struct PolicyViolation {
+    pub rule_id: String,
+    /// The OWNERS file to fix, relative to the repo root.
+    pub owners_file: Option<String>,
+    /// What the violation is about, e.g., the rule pattern or the offending owner. Unlike the
+    /// message, it doesn't change with unrelated details such as owner counts.
+    pub subject: String,
+    pub message: String,
+}
+
+impl PolicyFile {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<PolicyFile> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| {
+            anyhow!("Failed to read policy file '{}': {}", path.display(), error)
+        })?;
+        toml::from_str(&text).map_err(|error| {
+            anyhow!(
+                "Failed to parse policy file '{}': {}",
+                path.display(),
+                error
+            )
+        })
+    }
+
+    fn is_allowed(&self, violation: &PolicyViolation) -> bool {
+        self.allowed.iter().any(|allowed| allowed.allows(violation))
+    }
+}
+
+impl AllowedViolation {
+    fn allows(&self, violation: &PolicyViolation) -> bool {
+        self.rule == violation.rule_id
+            && Some(self.owners_file.as_str()) == violation.owners_file.as_deref()
+    }
+}
+
+/// Whether `pattern` is the `directory` itself or within it. Both start with `/`, and directories
+/// end with one.
+fn is_within(pattern: &str, directory: &str) -> bool {
+    let directory = directory.trim_end_matches('/');
+    pattern == directory || pattern.starts_with(&format!("{}/", directory))
+}
+
+fn collect_nodes<'a>(node: &'a TreeNode, nodes: &mut Vec<&'a TreeNode>) {
+    nodes.push(node);
+    for child in &node.children {
+        collect_nodes(child, nodes);
+    }
+}
+
+/// Evaluates every rule in the `policy_file` against the ownership resolved from the
+/// `owners_tree`, returning the violations which aren't allowed, sorted by OWNERS file.
+pub fn check_policy_file(
+    owners_tree: &OwnersTree,
+    implicit_inherit: bool,
+    policy_file: &PolicyFile,
+) -> anyhow::Result<Vec<PolicyViolation>> {
+    let root = &owners_tree.path;
+    let (codeowners, sources) = generate_codeowners_with_sources(owners_tree, implicit_inherit)?;
+    let rules = to_codeowners_rules(&codeowners);
+    let source_of = |pattern: &str| {
+        let key = if pattern == "*" { "/" } else { pattern };
+        sources
+            .get(key)
+            .cloned()
+            .flatten()
+            .map(|owners_file| relative_path_string(&owners_file, root))
+    };
+    let mut nodes = Vec::new();
+    collect_nodes(owners_tree, &mut nodes);
+
+    let mut violations = Vec::new();
+    for policy_rule in &policy_file.rules {
+        let mut violation = |owners_file: Option<String>, subject: String, message: String| {
+            violations.push(PolicyViolation {
+                rule_id: policy_rule.id.clone(),
+                owners_file,
+                subject,
+                message,
+            })
+        };
+        match &policy_rule.check {
+            PolicyCheck::RequiredOwners { path, owners } => {
+                let required_owners = owners
+                    .iter()
+                    .map(|owner| to_codeowners_owner(owner))
+                    .collect::<Vec<_>>();
+                // The closest directory rule above `path` applies to any of it without a rule of
+                // its own.
+                let covering_rule = rules
+                    .iter()
+                    .filter(|rule| {
+                        rule.pattern == "*"
+                            || (rule.pattern.ends_with('/') && is_within(path, &rule.pattern))
+                    })
+                    .last();
+                let applicable_rules = rules
+                    .iter()
+                    .filter(|rule| rule.pattern != "*" && is_within(&rule.pattern, path))
+                    .chain(covering_rule);
+                let mut checked = HashSet::new();
+                for rule in applicable_rules {
+                    if !checked.insert(&rule.pattern) {
+                        continue;
+                    }
+                    for owner in &required_owners {
+                        if !rule.owners.contains(owner) {
+                            violation(
+                                source_of(&rule.pattern),
+                                format!("{} {}", rule.pattern, owner),
+                                format!("{} doesn't include {}", rule.pattern, owner),
+                            );
+                        }
+                    }
+                }
+            }
+            PolicyCheck::NoEmails => {
+                for node in &nodes {
+                    let owners_config = &node.owners_config;
+                    let emails = owners_config
+                        .all_files
+                        .owners
+                        .iter()
+                        .chain(
+                            owners_config
+                                .pattern_overrides
+                                .values()
+                                .flat_map(|owners_set| &owners_set.owners),
+                        )
+                        .filter(|owner| is_email(owner))
+                        .collect::<BTreeSet<_>>();
+                    for email in emails {
+                        violation(
+                            node.owners_file
+                                .as_ref()
+                                .map(|owners_file| relative_path_string(owners_file, root)),
+                            email.to_string(),
+                            format!("{} is an email address", email),
+                        );
+                    }
+                }
+            }
+            PolicyCheck::InheritFalseOnlyIn { directories } => {
+                for node in &nodes {
+                    let directory = directory_string(&node.path, root);
+                    if directories
+                        .iter()
+                        .any(|allowed| is_within(&directory, allowed))
+                    {
+                        continue;
+                    }
+                    let owners_config = &node.owners_config;
+                    let mut sections = owners_config
+                        .pattern_overrides
+                        .iter()
+                        .filter(|(_, owners_set)| owners_set.inherit == Some(false))
+                        .map(|(pattern, _)| format!("{}{}", directory, pattern))
+                        .collect::<Vec<_>>();
+                    sections.sort();
+                    if owners_config.all_files.inherit == Some(false) {
+                        sections.insert(0, directory.clone());
+                    }
+                    for section in sections {
+                        violation(
+                            node.owners_file
+                                .as_ref()
+                                .map(|owners_file| relative_path_string(owners_file, root)),
+                            section.clone(),
+                            format!("set inherit = false for {}", section),
+                        );
+                    }
+                }
+            }
+            PolicyCheck::MinOwners(min_owners) => {
+                for min_owners_violation in
+                    check_min_owners(owners_tree, implicit_inherit, min_owners)?
+                {
+                    let PolicyViolation {
+                        owners_file,
+                        subject,
+                        message,
+                        ..
+                    } = min_owners_violation.to_policy_violation(&policy_rule.id, min_owners);
+                    violation(owners_file, subject, message);
+                }
+            }
+        }
+    }
+
+    for allowed in &policy_file.allowed {
+        if !violations.iter().any(|violation| allowed.allows(violation)) {
+            warn!(
+                "Allowed violation of {} in {} no longer occurs, and can be removed",
+                allowed.rule, allowed.owners_file
+            );
+        }
+    }
+    violations.retain(|violation| !policy_file.is_allowed(violation));
+    // Stable, so each file's violations stay in rule order.
+    violations.sort_by(|a, b| a.owners_file.cmp(&b.owners_file));
+    Ok(violations)
+}
+
+pub fn to_policy_violations_string(violations: &[PolicyViolation]) -> String {
+    let mut lines = vec![format!(
+        "{}:",
+        count(violations.len(), "policy violation", "policy violations")
+    )];
+    for violation in violations {
+        lines.push(format!(
+            "  [{}] {}: {}",
+            violation.rule_id,
+            violation
+                .owners_file
+                .as_deref()
+                .unwrap_or("(no OWNERS file)"),
+            violation.message
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allow_filter::FilterGitMetadata;
+    use crate::file_source::InMemoryFiles;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+    use crate::policy::{
+        check_min_owners, check_policy_file, to_policy_violations_string, MinOwnersPolicy,
+        PolicyFile, MIN_OWNERS_RULE_ID,
+    };
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn min_owners() -> anyhow::Result<()> {
+        let files = InMemoryFiles::new([
+            (
+                "/repo/OWNERS",
+                indoc! {"\
+                    ada.lovelace
+                    grace.hopper
+                    "
+                },
+            ),
+            (
+                "/repo/foo/OWNERS",
+                indoc! {"\
+                    set inherit = false
+                    margaret.hamilton
+                    [*.md]
+                    @org/docs
+                    "
+                },
+            ),
+            (
+                "/repo/bar/OWNERS",
+                indoc! {"\
+                    set inherit = false
+                    katherine.johnson
+                    "
+                },
+            ),
+            (
+                "/repo/baz/OWNERS",
+                indoc! {"\
+                    set inherit = false
+                    @org/platform
+                    "
+                },
+            ),
+        ]);
+        let tree = OwnersTree::load_from_files(
+            "/repo",
+            &LoadOptions {
+                allow_filter: &FilterGitMetadata {},
+                owners_file_names: &["OWNERS"],
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &files,
+                cache: None,
+            },
+        )?;
+        let policy = MinOwnersPolicy {
+            individuals: Some(2),
+            teams: Some(1),
+            exempt: vec!["/bar/".to_string()],
+        };
+
+        let violations = check_min_owners(&tree, true, &policy)?
+            .into_iter()
+            .map(|violation| violation.to_policy_violation(MIN_OWNERS_RULE_ID, &policy))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to_policy_violations_string(&violations),
+            indoc! {"\
+                1 policy violation:
+                  [min-owners] /foo/OWNERS: /foo/ has 1 individual and 0 teams, fewer than 2 individuals or 1 team"
+            }
+        );
+
+        // Only individuals count
+        let policy = MinOwnersPolicy {
+            teams: None,
+            exempt: vec![],
+            ..policy
+        };
+        let violations = check_min_owners(&tree, true, &policy)?;
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.pattern.as_str())
+                .collect::<Vec<_>>(),
+            vec!["/bar/", "/baz/", "/foo/", "/foo/*.md"]
+        );
+        assert_eq!(violations[1].owners_file.as_deref(), Some("/baz/OWNERS"));
+
+        // No minimums, no violations
+        assert!(check_min_owners(&tree, true, &MinOwnersPolicy::default())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn policy_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "policy.toml",
+            indoc! {r#"
+                [[rules]]
+                id = "security-team"
+                kind = "required-owners"
+                path = "/security/"
+                owners = ["@org/security"]
+
+                [[rules]]
+                id = "no-emails"
+                kind = "no-emails"
+
+                [[rules]]
+                id = "no-inherit-false"
+                kind = "inherit-false-only-in"
+                directories = ["/vendor/"]
+
+                [[rules]]
+                id = "two-owners"
+                kind = "min-owners"
+                individuals = 2
+
+                [[allowed]]
+                rule = "no-emails"
+                owners-file = "/legacy/OWNERS"
+
+                [[allowed]]
+                rule = "two-owners"
+                owners-file = "/removed/OWNERS"
+                "#
+            },
+        )?;
+        let policy_file = PolicyFile::from_file(temp_dir.path().join("policy.toml"))?;
+
+        let files = InMemoryFiles::new([
+            (
+                "/repo/OWNERS",
+                indoc! {"\
+                    ada.lovelace
+                    grace.hopper
+                    "
+                },
+            ),
+            (
+                "/repo/security/OWNERS",
+                indoc! {"\
+                    @org/security
+                    [*.md]
+                    set inherit = false
+                    mary.jackson
+                    "
+                },
+            ),
+            (
+                "/repo/security/keys/OWNERS",
+                "katherine.johnson@example.com\n",
+            ),
+            ("/repo/legacy/OWNERS", "alan.turing@example.com\n"),
+            (
+                "/repo/vendor/lib/OWNERS",
+                indoc! {"\
+                    set inherit = false
+                    margaret.hamilton
+                    grace.hopper
+                    "
+                },
+            ),
+        ]);
+        let tree = OwnersTree::load_from_files(
+            "/repo",
+            &LoadOptions {
+                allow_filter: &FilterGitMetadata {},
+                owners_file_names: &["OWNERS"],
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &files,
+                cache: None,
+            },
+        )?;
+
+        let violations = check_policy_file(&tree, true, &policy_file)?;
+        assert_eq!(
+            to_policy_violations_string(&violations),
+            indoc! {"\
+                4 policy violations:
+                  [security-team] /security/OWNERS: /security/*.md doesn't include @org/security
+                  [no-inherit-false] /security/OWNERS: set inherit = false for /security/*.md
+                  [two-owners] /security/OWNERS: /security/*.md has 1 individual and 0 teams, fewer than 2 individuals
+                  [no-emails] /security/keys/OWNERS: katherine.johnson@example.com is an email address"
+            }
+        );
+
+        // Without an OWNERS file of its own, the directory is covered by its parent's rule
+        let policy_file: PolicyFile = toml::from_str(indoc! {r#"
+            [[rules]]
+            id = "docs-team"
+            kind = "required-owners"
+            path = "/docs/"
+            owners = ["org/docs"]
+            "#
+        })?;
+        let violations = check_policy_file(&tree, true, &policy_file)?;
+        assert_eq!(
+            to_policy_violations_string(&violations),
+            indoc! {"\
+                1 policy violation:
+                  [docs-team] /OWNERS: * doesn't include @org/docs"
+            }
+        );
+        Ok(())
+    }
+}
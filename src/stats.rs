@@ -0,0 +1,368 @@
+use crate::codeowners::{generate_codeowners, to_codeowners_rules, CodeownersMatcher};
+use crate::owners_set::OwnersSet;
+use crate::owners_tree::{OwnersTree, TreeNode};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Summary statistics about how ownership is distributed through a repository.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct OwnershipStats {
+    pub owners_files: usize,
+    pub files: usize,
+    pub unowned_files: usize,
+    /// The number of directories with each number of owners, where a directory's owners are the
+    /// owners of any of the files directly in it.
+    pub owners_per_directory: BTreeMap<usize, usize>,
+    /// Sorted by number of files, most first.
+    pub files_per_owner: Vec<OwnerFiles>,
+    /// Directories with exactly one owner, i.e., a bus factor of one.
+    pub single_owner_directories: Vec<String>,
+    /// The directories of the longest chain of OWNERS files each inheriting from the previous,
+    /// from the top down.
+    pub deepest_inheritance_chain: Vec<String>,
+    /// Sorted by the number of OWNERS files including each, most first.
+    pub most_included: Vec<IncludedFile>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct OwnerFiles {
+    pub owner: String,
+    pub files: usize,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct IncludedFile {
+    pub path: String,
+    /// The number of OWNERS files including this one, directly or indirectly.
+    pub included_by: usize,
+}
+
+/// Computes statistics for the `owners_tree`, over the tracked `files`, relative to the root of
+/// the tree.
+///
+/// `owners_files` are the OWNERS files loaded into the tree, as absolute paths, each with the
+/// files it includes.
+pub fn compute_stats<I, P>(
+    owners_tree: &OwnersTree,
+    implicit_inherit: bool,
+    owners_files: &BTreeMap<PathBuf, Vec<PathBuf>>,
+    files: I,
+) -> anyhow::Result<OwnershipStats>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let root = &owners_tree.path;
+    let codeowners = generate_codeowners(owners_tree, implicit_inherit)?;
+    let matcher = CodeownersMatcher::new(to_codeowners_rules(&codeowners))?;
+
+    let mut file_count = 0;
+    let mut unowned_files = 0;
+    let mut files_per_owner: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut directory_owners: BTreeMap<PathBuf, BTreeSet<&str>> = BTreeMap::new();
+    for file in files {
+        let file = file.as_ref();
+        let owners = matcher.owners(file);
+        file_count += 1;
+        if owners.is_empty() {
+            unowned_files += 1;
+        }
+        let directory = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let directory_owners = directory_owners.entry(directory).or_default();
+        for owner in owners {
+            *files_per_owner.entry(owner).or_default() += 1;
+            directory_owners.insert(owner);
+        }
+    }
+
+    let mut owners_per_directory = BTreeMap::new();
+    for owners in directory_owners.values() {
+        *owners_per_directory.entry(owners.len()).or_default() += 1;
+    }
+    let single_owner_directories = directory_owners
+        .iter()
+        .filter(|(_, owners)| owners.len() == 1)
+        .map(|(directory, _)| directory_string(directory))
+        .collect();
+
+    let mut files_per_owner = files_per_owner
+        .into_iter()
+        .map(|(owner, files)| OwnerFiles {
+            owner: owner.to_string(),
+            files,
+        })
+        .collect::<Vec<_>>();
+    // Stable, so ties stay sorted by owner.
+    files_per_owner.sort_by(|a, b| b.files.cmp(&a.files));
+
+    let owners_directories = owners_files
+        .keys()
+        .filter_map(|owners_file| owners_file.parent())
+        .collect::<HashSet<_>>();
+    let mut deepest_inheritance_chain = Vec::new();
+    find_deepest_chain(
+        owners_tree,
+        &owners_directories,
+        implicit_inherit,
+        Vec::new(),
+        &mut deepest_inheritance_chain,
+    );
+    let deepest_inheritance_chain = deepest_inheritance_chain
+        .into_iter()
+        .map(|directory| directory_string(directory.strip_prefix(root).unwrap_or(directory)))
+        .collect();
+
+    let mut included_by: BTreeMap<&Path, usize> = BTreeMap::new();
+    for included_file in owners_files.values().flatten() {
+        *included_by.entry(included_file).or_default() += 1;
+    }
+    let mut most_included = included_by
+        .into_iter()
+        .map(|(path, included_by)| IncludedFile {
+            path: match path.strip_prefix(root) {
+                Ok(relative_path) => format!("/{}", relative_path.to_string_lossy()),
+                Err(_) => path.to_string_lossy().to_string(),
+            },
+            included_by,
+        })
+        .collect::<Vec<_>>();
+    most_included.sort_by(|a, b| b.included_by.cmp(&a.included_by));
+
+    Ok(OwnershipStats {
+        owners_files: owners_files.len(),
+        files: file_count,
+        unowned_files,
+        owners_per_directory,
+        files_per_owner,
+        single_owner_directories,
+        deepest_inheritance_chain,
+        most_included,
+    })
+}
+
+fn inherits(owners_set: &OwnersSet, implicit_inherit: bool) -> bool {
+    owners_set.inherit == Some(true) || (implicit_inherit && owners_set.inherit.is_none())
+}
+
+/// Tracks the `chain` of inheriting OWNERS files down to `node`, keeping the longest found in
+/// `deepest`. Nodes without an OWNERS file neither extend nor break the chain.
+fn find_deepest_chain<'a>(
+    node: &'a TreeNode,
+    owners_directories: &HashSet<&Path>,
+    implicit_inherit: bool,
+    mut chain: Vec<&'a Path>,
+    deepest: &mut Vec<&'a Path>,
+) {
+    if owners_directories.contains(node.path.as_path()) {
+        if !inherits(&node.owners_config.all_files, implicit_inherit) {
+            chain.clear();
+        }
+        chain.push(&node.path);
+        if chain.len() > deepest.len() {
+            *deepest = chain.clone();
+        }
+    }
+    for child in &node.children {
+        find_deepest_chain(
+            child,
+            owners_directories,
+            implicit_inherit,
+            chain.clone(),
+            deepest,
+        );
+    }
+}
+
+/// Renders a directory, relative to the repo root, in the same form as CODEOWNERS patterns.
+fn directory_string(directory: &Path) -> String {
+    if directory.as_os_str().is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", directory.to_string_lossy())
+    }
+}
+
+/// Renders the stats as text, listing at most `top` owners and included files.
+pub fn to_stats_string(stats: &OwnershipStats, top: usize) -> String {
+    let mut lines = vec![
+        format!("OWNERS files: {}", stats.owners_files),
+        format!("Files: {} ({} unowned)", stats.files, stats.unowned_files),
+        "Directories by number of owners:".to_string(),
+    ];
+    for (owners, directories) in &stats.owners_per_directory {
+        lines.push(format!("  {}: {}", owners, directories));
+    }
+
+    lines.push(format!(
+        "Files per owner (top {} of {}):",
+        top.min(stats.files_per_owner.len()),
+        stats.files_per_owner.len()
+    ));
+    for owner_files in stats.files_per_owner.iter().take(top) {
+        lines.push(format!("  {}: {}", owner_files.owner, owner_files.files));
+    }
+
+    lines.push(format!(
+        "Directories with a single owner: {}",
+        stats.single_owner_directories.len()
+    ));
+    for directory in &stats.single_owner_directories {
+        lines.push(format!("  {}", directory));
+    }
+
+    lines.push(format!(
+        "Deepest inheritance chain: {}",
+        stats.deepest_inheritance_chain.len()
+    ));
+    for directory in &stats.deepest_inheritance_chain {
+        lines.push(format!("  {}", directory));
+    }
+
+    lines.push(format!(
+        "Most included files (top {} of {}):",
+        top.min(stats.most_included.len()),
+        stats.most_included.len()
+    ));
+    for included_file in stats.most_included.iter().take(top) {
+        lines.push(format!(
+            "  {}: {}",
+            included_file.path, included_file.included_by
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allow_filter::FilterGitMetadata;
+    use crate::cache::ParseCache;
+    use crate::file_source::WorkingTree;
+    use crate::owners_tree::{LoadOptions, OwnersTree, SubmoduleMode};
+    use crate::stats::{compute_stats, to_stats_string, IncludedFile, OwnerFiles};
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stats() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        create_test_file(&temp_dir, "OWNERS", "ada.lovelace\n")?;
+        create_test_file(
+            &temp_dir,
+            "foo/OWNERS",
+            indoc! {"\
+                include /shared/OWNERS
+                [*.md]
+                set inherit = false
+                mary.jackson
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "foo/bar/OWNERS", "include /shared/OWNERS\n")?;
+        create_test_file(
+            &temp_dir,
+            "baz/OWNERS",
+            indoc! {"\
+                set inherit = false
+                katherine.johnson
+                "
+            },
+        )?;
+        create_test_file(&temp_dir, "shared/OWNERS", "grace.hopper\n")?;
+
+        let root = temp_dir.path().canonicalize()?;
+        let cache = ParseCache::in_memory();
+        let tree = OwnersTree::load_from_files(
+            root,
+            &LoadOptions {
+                allow_filter: &FilterGitMetadata {},
+                owners_file_names: &["OWNERS"],
+                submodule_mode: SubmoduleMode::Ignore,
+                source: &WorkingTree {},
+                cache: Some(&cache),
+            },
+        )?;
+        let files = [
+            "README.md",
+            "foo/a.rs",
+            "foo/README.md",
+            "foo/bar/b.rs",
+            "baz/c.rs",
+        ];
+        let stats = compute_stats(&tree, true, &cache.included_files(), files)?;
+
+        assert_eq!(stats.owners_files, 5);
+        assert_eq!(stats.files, 5);
+        assert_eq!(stats.unowned_files, 0);
+        assert_eq!(
+            stats.owners_per_directory,
+            BTreeMap::from([(1, 2), (2, 1), (3, 1)])
+        );
+        assert_eq!(
+            stats.files_per_owner,
+            vec![
+                OwnerFiles {
+                    owner: "@ada.lovelace".to_string(),
+                    files: 3
+                },
+                OwnerFiles {
+                    owner: "@grace.hopper".to_string(),
+                    files: 2
+                },
+                OwnerFiles {
+                    owner: "@katherine.johnson".to_string(),
+                    files: 1
+                },
+                OwnerFiles {
+                    owner: "@mary.jackson".to_string(),
+                    files: 1
+                },
+            ]
+        );
+        assert_eq!(stats.single_owner_directories, vec!["/", "/baz/"]);
+        assert_eq!(
+            stats.deepest_inheritance_chain,
+            vec!["/", "/foo/", "/foo/bar/"]
+        );
+        assert_eq!(
+            stats.most_included,
+            vec![IncludedFile {
+                path: "/shared/OWNERS".to_string(),
+                included_by: 2
+            }]
+        );
+
+        assert_eq!(
+            to_stats_string(&stats, 2),
+            indoc! {"\
+                OWNERS files: 5
+                Files: 5 (0 unowned)
+                Directories by number of owners:
+                  1: 2
+                  2: 1
+                  3: 1
+                Files per owner (top 2 of 4):
+                  @ada.lovelace: 3
+                  @grace.hopper: 2
+                Directories with a single owner: 2
+                  /
+                  /baz/
+                Deepest inheritance chain: 3
+                  /
+                  /foo/
+                  /foo/bar/
+                Most included files (top 1 of 1):
+                  /shared/OWNERS: 2"
+            }
+        );
+
+        // Without implicit inheritance, every chain is a single OWNERS file
+        let stats = compute_stats(&tree, false, &cache.included_files(), Vec::<&Path>::new())?;
+        assert_eq!(stats.deepest_inheritance_chain.len(), 1);
+        assert!(stats.owners_per_directory.is_empty());
+        Ok(())
+    }
+}
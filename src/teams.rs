@@ -0,0 +1,282 @@
+use crate::codeowners::is_team;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How owners are adjusted using the team membership file.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum TeamMode {
+    /// Drop individual owners who are members of a team owning the same rule.
+    #[default]
+    Collapse,
+    /// Replace teams with their members, for hosts which don't support teams.
+    Expand,
+}
+
+impl FromStr for TeamMode {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<TeamMode> {
+        match text {
+            "collapse" => Ok(TeamMode::Collapse),
+            "expand" => Ok(TeamMode::Expand),
+            _ => Err(anyhow!(
+                "Invalid team mode '{}'. Expected 'collapse' or 'expand'.",
+                text
+            )),
+        }
+    }
+}
+
+/// Offline team membership, e.g., exported from the organisation's settings, keyed by team.
+///
+/// Teams and members may be written with or without a leading `@`.
+#[derive(Deserialize, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TeamMembership {
+    teams: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Owners of a rule, replaced by, or with, a team.
+#[derive(PartialEq, Debug)]
+pub struct TeamChange {
+    pub pattern: String,
+    pub team: String,
+    pub members: Vec<String>,
+}
+
+/// Owners are compared without any leading `@`, as CODEOWNERS adds one anyway.
+fn normalize(owner: &str) -> &str {
+    owner.trim_start_matches('@')
+}
+
+impl TeamMembership {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<TeamMembership> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| {
+            anyhow!(
+                "Failed to read team membership file '{}': {}",
+                path.display(),
+                error
+            )
+        })?;
+        let membership: TeamMembership = toml::from_str(&text).map_err(|error| {
+            anyhow!(
+                "Failed to parse team membership file '{}': {}",
+                path.display(),
+                error
+            )
+        })?;
+        Ok(membership.normalized())
+    }
+
+    fn normalized(self) -> TeamMembership {
+        let teams = self
+            .teams
+            .into_iter()
+            .map(|(team, members)| {
+                let members = members
+                    .iter()
+                    .map(|member| normalize(member).to_string())
+                    .collect();
+                (normalize(&team).to_string(), members)
+            })
+            .collect();
+        TeamMembership { teams }
+    }
+
+    fn members(&self, team: &str) -> Option<&BTreeSet<String>> {
+        self.teams.get(normalize(team))
+    }
+
+    /// Drops individual owners already covered by a team owning the same rule. Returns what was
+    /// dropped, by rule and team.
+    pub fn collapse_team_members(
+        &self,
+        codeowners: &mut HashMap<String, HashSet<String>>,
+    ) -> Vec<TeamChange> {
+        let mut changes = Vec::new();
+        for (pattern, owners) in codeowners.iter_mut() {
+            let teams = owners
+                .iter()
+                .filter_map(|owner| Some((owner.clone(), self.members(owner)?)))
+                .collect::<BTreeMap<_, _>>();
+            for (team, members) in teams {
+                let mut covered = owners
+                    .iter()
+                    .filter(|owner| members.contains(normalize(owner)))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if covered.is_empty() {
+                    continue;
+                }
+                covered.sort();
+                for owner in &covered {
+                    owners.remove(owner);
+                }
+                changes.push(TeamChange {
+                    pattern: pattern.clone(),
+                    team,
+                    members: covered,
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.pattern.cmp(&b.pattern).then(a.team.cmp(&b.team)));
+        changes
+    }
+
+    /// Replaces teams with their members, failing for teams without known members, which would
+    /// otherwise be left in place. Returns what was replaced, by rule and team.
+    pub fn expand_teams(
+        &self,
+        codeowners: &mut HashMap<String, HashSet<String>>,
+    ) -> anyhow::Result<Vec<TeamChange>> {
+        let mut changes = Vec::new();
+        for (pattern, owners) in codeowners.iter_mut() {
+            let teams = owners
+                .iter()
+                .filter(|owner| is_team(owner))
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            for team in teams {
+                let members = self.members(&team).ok_or_else(|| {
+                    anyhow!(
+                        "Team '{}', owning '{}', isn't in the team membership file",
+                        team,
+                        pattern
+                    )
+                })?;
+                owners.remove(&team);
+                let existing = owners
+                    .iter()
+                    .map(|owner| normalize(owner).to_string())
+                    .collect::<HashSet<_>>();
+                owners.extend(
+                    members
+                        .iter()
+                        .filter(|member| !existing.contains(*member))
+                        .cloned(),
+                );
+                changes.push(TeamChange {
+                    pattern: pattern.clone(),
+                    team,
+                    members: members.iter().cloned().collect(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.pattern.cmp(&b.pattern).then(a.team.cmp(&b.team)));
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::teams::{TeamChange, TeamMembership};
+    use crate::test_utils::create_test_file;
+    use indoc::indoc;
+    use std::collections::{HashMap, HashSet};
+    use tempfile::tempdir;
+
+    fn owners(owners: &[&str]) -> HashSet<String> {
+        owners.iter().map(|owner| owner.to_string()).collect()
+    }
+
+    fn load_membership() -> anyhow::Result<TeamMembership> {
+        let temp_dir = tempdir()?;
+        create_test_file(
+            &temp_dir,
+            "teams.toml",
+            indoc! {r#"
+                [teams]
+                "@org/platform" = ["ada.lovelace", "@grace.hopper"]
+                "org/docs" = ["mary.jackson"]
+                "#
+            },
+        )?;
+        TeamMembership::from_file(temp_dir.path().join("teams.toml"))
+    }
+
+    #[test]
+    fn collapse_team_members() -> anyhow::Result<()> {
+        let membership = load_membership()?;
+        let mut codeowners = HashMap::from([
+            (
+                "/".to_string(),
+                owners(&[
+                    "org/platform",
+                    "@ada.lovelace",
+                    "grace.hopper",
+                    "alan.turing",
+                ]),
+            ),
+            (
+                "/docs/".to_string(),
+                owners(&["@org/docs", "mary.jackson", "ada.lovelace"]),
+            ),
+            // Not covered by a team owning the rule
+            ("/foo/".to_string(), owners(&["grace.hopper"])),
+        ]);
+
+        let changes = membership.collapse_team_members(&mut codeowners);
+
+        assert_eq!(
+            changes,
+            vec![
+                TeamChange {
+                    pattern: "/".to_string(),
+                    team: "org/platform".to_string(),
+                    members: vec!["@ada.lovelace".to_string(), "grace.hopper".to_string()],
+                },
+                TeamChange {
+                    pattern: "/docs/".to_string(),
+                    team: "@org/docs".to_string(),
+                    members: vec!["mary.jackson".to_string()],
+                },
+            ]
+        );
+        assert_eq!(
+            codeowners,
+            HashMap::from([
+                ("/".to_string(), owners(&["org/platform", "alan.turing"])),
+                ("/docs/".to_string(), owners(&["@org/docs", "ada.lovelace"])),
+                ("/foo/".to_string(), owners(&["grace.hopper"])),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expand_teams() -> anyhow::Result<()> {
+        let membership = load_membership()?;
+        let mut codeowners = HashMap::from([
+            (
+                "/".to_string(),
+                owners(&["@org/platform", "@ada.lovelace", "alan.turing"]),
+            ),
+            ("/docs/".to_string(), owners(&["org/docs"])),
+        ]);
+
+        let changes = membership.expand_teams(&mut codeowners)?;
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            codeowners,
+            HashMap::from([
+                (
+                    "/".to_string(),
+                    owners(&["@ada.lovelace", "grace.hopper", "alan.turing"])
+                ),
+                ("/docs/".to_string(), owners(&["mary.jackson"])),
+            ])
+        );
+
+        // Teams without known members can't be expanded
+        let mut codeowners = HashMap::from([("/".to_string(), owners(&["org/unknown"]))]);
+        assert!(membership.expand_teams(&mut codeowners).is_err());
+        Ok(())
+    }
+}
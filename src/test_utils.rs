@@ -1,3 +1,4 @@
+use git2::{IndexAddOption, IndexEntry, IndexTime, Oid, Repository, Signature};
 use std::fs;
 use tempfile::TempDir;
 
@@ -7,3 +8,55 @@ pub fn create_test_file(temp_dir: &TempDir, path: &str, contents: &str) -> anyho
     fs::write(full_path, contents)?;
     Ok(())
 }
+
+/// Registers `path` as a submodule in the index, without going through the usual submodule
+/// setup, which needs a remote to clone from.
+pub fn add_gitlink(repo: &Repository, path: &str) -> anyhow::Result<()> {
+    let mut index = repo.index()?;
+    index.add(&IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0o160000,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id: Oid::from_str("0123456789abcdef0123456789abcdef01234567")?,
+        flags: path.len() as u16,
+        flags_extended: 0,
+        path: path.as_bytes().to_vec(),
+    })?;
+    index.write()?;
+    Ok(())
+}
+
+/// Commits everything in the working tree, returning the commit id.
+pub fn commit_all(repo: &Repository, message: &str) -> anyhow::Result<String> {
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    // Stages deletions as well
+    index.update_all(["*"], None)?;
+    index.write()?;
+    commit_index(repo, message)
+}
+
+/// Commits the index as it is, returning the commit id.
+pub fn commit_index(repo: &Repository, message: &str) -> anyhow::Result<String> {
+    let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+    let signature = Signature::now("Ada Lovelace", "ada@example.com")?;
+    let parents = match repo.head() {
+        Ok(head) => vec![head.peel_to_commit()?],
+        Err(_) => vec![],
+    };
+    let parents = parents.iter().collect::<Vec<_>>();
+    let oid = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    Ok(oid.to_string())
+}
@@ -0,0 +1,227 @@
+use anyhow::anyhow;
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Regenerates on every relevant change under `repo_root`, printing the changes to the generated
+/// CODEOWNERS since the previous run.
+///
+/// `regenerate` returns the CODEOWNERS rules, as text. Errors from it are reported, and the
+/// previous output kept, until the next change. This only returns if watching fails.
+pub fn watch<R, G>(
+    repo_root: &Path,
+    debounce: Duration,
+    is_relevant: R,
+    mut regenerate: G,
+) -> anyhow::Result<()>
+where
+    R: Fn(&Path) -> bool,
+    G: FnMut() -> anyhow::Result<String>,
+{
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(repo_root, RecursiveMode::Recursive)?;
+    info!("Watching {:?} for changes", repo_root);
+
+    let mut previous = match regenerate() {
+        Ok(codeowners) => {
+            println!("Generated {} rules", codeowners.lines().count());
+            Some(codeowners)
+        }
+        Err(error) => {
+            error!("{:#}", error);
+            None
+        }
+    };
+    loop {
+        let changed = wait_for_changes(&receiver, debounce, &is_relevant)?;
+        debug!("Changed: {:?}", changed);
+        match regenerate() {
+            Ok(codeowners) => {
+                match &previous {
+                    Some(previous) => print_diff(previous, &codeowners),
+                    None => println!("Generated {} rules", codeowners.lines().count()),
+                }
+                previous = Some(codeowners);
+            }
+            Err(error) => error!("{:#}", error),
+        }
+    }
+}
+
+/// Blocks until there is at least one relevant change, then until there have been no further
+/// events for `debounce`, so that bursts of changes only trigger a single regeneration.
+fn wait_for_changes<R: Fn(&Path) -> bool>(
+    receiver: &Receiver<notify::Result<Event>>,
+    debounce: Duration,
+    is_relevant: &R,
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    while changed.is_empty() {
+        let event = receiver
+            .recv()
+            .map_err(|_| anyhow!("Stopped receiving file system events."))?;
+        add_relevant_paths(event, is_relevant, &mut changed);
+    }
+    loop {
+        match receiver.recv_timeout(debounce) {
+            Ok(event) => add_relevant_paths(event, is_relevant, &mut changed),
+            Err(RecvTimeoutError::Timeout) => return Ok(changed),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("Stopped receiving file system events."))
+            }
+        }
+    }
+}
+
+fn add_relevant_paths<R: Fn(&Path) -> bool>(
+    event: notify::Result<Event>,
+    is_relevant: &R,
+    changed: &mut BTreeSet<PathBuf>,
+) {
+    let event = match event {
+        Ok(event) => event,
+        Err(error) => {
+            warn!("Error watching for changes: {}", error);
+            return;
+        }
+    };
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+    changed.extend(event.paths.into_iter().filter(|path| is_relevant(path)));
+}
+
+/// Lines removed from `previous` and added in `current`, prefixed with `-` and `+` respectively.
+///
+/// CODEOWNERS rules are sorted, so lines are never reordered and a single pass suffices.
+fn diff_lines(previous: &str, current: &str) -> Vec<String> {
+    let previous_lines = previous.lines().collect::<Vec<_>>();
+    let current_lines = current.lines().collect::<Vec<_>>();
+    let previous_set = previous_lines.iter().collect::<HashSet<_>>();
+    let current_set = current_lines.iter().collect::<HashSet<_>>();
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < previous_lines.len() && j < current_lines.len() {
+        if previous_lines[i] == current_lines[j] {
+            i += 1;
+            j += 1;
+        } else if !current_set.contains(&previous_lines[i])
+            && (previous_set.contains(&current_lines[j]) || previous_lines[i] < current_lines[j])
+        {
+            // Both changed, so keep to sorted order, which places a changed rule's removal
+            // before its addition.
+            diff.push(format!("-{}", previous_lines[i]));
+            i += 1;
+        } else if !previous_set.contains(&current_lines[j]) {
+            diff.push(format!("+{}", current_lines[j]));
+            j += 1;
+        } else {
+            // Moved, which sorted rules shouldn't do. Still produce a valid, if not minimal, diff.
+            diff.push(format!("-{}", previous_lines[i]));
+            i += 1;
+        }
+    }
+    diff.extend(previous_lines[i..].iter().map(|line| format!("-{line}")));
+    diff.extend(current_lines[j..].iter().map(|line| format!("+{line}")));
+    diff
+}
+
+fn print_diff(previous: &str, current: &str) {
+    let diff = diff_lines(previous, current);
+    if diff.is_empty() {
+        println!("No changes to CODEOWNERS");
+    } else {
+        println!("CODEOWNERS changes:\n{}", diff.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::{diff_lines, wait_for_changes};
+    use indoc::indoc;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+    use notify::{Event, EventKind};
+    use std::collections::BTreeSet;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_diff_lines() {
+        let previous = indoc! {"\
+            * @ada.lovelace
+            /docs/ @grace.hopper
+            /foo/ @margaret.hamilton
+            /legacy/ @alan.turing
+            "
+        };
+        let current = indoc! {"\
+            * @ada.lovelace
+            /bar/ @katherine.johnson
+            /docs/ @grace.hopper @mary.jackson
+            /foo/ @margaret.hamilton
+            "
+        };
+
+        let expected = vec![
+            "+/bar/ @katherine.johnson",
+            "-/docs/ @grace.hopper",
+            "+/docs/ @grace.hopper @mary.jackson",
+            "-/legacy/ @alan.turing",
+        ];
+
+        assert_eq!(diff_lines(previous, current), expected);
+        assert!(diff_lines(current, current).is_empty());
+    }
+
+    #[test]
+    fn test_wait_for_changes_debounces() -> anyhow::Result<()> {
+        let (sender, receiver) = channel();
+        let is_relevant = |path: &Path| path.ends_with("OWNERS");
+        let event =
+            |kind: EventKind, path: &str| Ok(Event::new(kind).add_path(PathBuf::from(path)));
+
+        sender.send(event(
+            EventKind::Create(CreateKind::File),
+            "/repo/README.md",
+        ))?;
+        sender.send(event(EventKind::Access(AccessKind::Any), "/repo/OWNERS"))?;
+        sender.send(Err(notify::Error::generic("transient")))?;
+        sender.send(event(
+            EventKind::Modify(ModifyKind::Any),
+            "/repo/foo/OWNERS",
+        ))?;
+        let burst_sender = sender.clone();
+        let burst = thread::spawn(move || {
+            for _ in 0..3 {
+                thread::sleep(Duration::from_millis(20));
+                burst_sender
+                    .send(event(
+                        EventKind::Modify(ModifyKind::Any),
+                        "/repo/bar/OWNERS",
+                    ))
+                    .unwrap();
+            }
+        });
+
+        let changed = wait_for_changes(&receiver, Duration::from_millis(200), &is_relevant)?;
+        burst.join().unwrap();
+        let expected = [
+            PathBuf::from("/repo/bar/OWNERS"),
+            PathBuf::from("/repo/foo/OWNERS"),
+        ]
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+        assert_eq!(changed, expected);
+
+        drop(sender);
+        assert!(wait_for_changes(&receiver, Duration::from_millis(200), &is_relevant).is_err());
+        Ok(())
+    }
+}